@@ -1,5 +1,10 @@
 use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
 use std::num::ParseIntError;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
 fn find_combination(target_value: u32, data: &[u32], k: usize) -> Result<Vec<u32>, String> {
     for combination in Itertools::combinations(data.iter().copied(), k) {
@@ -12,19 +17,481 @@ fn find_combination(target_value: u32, data: &[u32], k: usize) -> Result<Vec<u32
     ))
 }
 
+fn sorted_copy(data: &[u32]) -> Vec<u32> {
+    let mut sorted = data.to_vec();
+    sorted.sort_unstable();
+    sorted
+}
+
+// The fast solvers below work on signed amounts so that they can be shared between the plain
+// puzzle input and ledgers with refunds in them.  Sums and differences are taken in `i128`:
+// any `i64` amount is accepted, and adding up k of them can't overflow that.
+fn sorted_amounts<T: Copy + Into<i64>>(data: &[T]) -> Vec<i64> {
+    let mut sorted: Vec<i64> = data.iter().map(|&value| value.into()).collect();
+    sorted.sort_unstable();
+    sorted
+}
+
+// Single pass over the data, remembering every value seen so far.  Duplicates are handled
+// naturally since a value is only looked up before it's inserted.
+fn find_pair_hashset(target_value: i64, sorted: &[i64]) -> Result<Vec<i64>, String> {
+    let mut seen: HashSet<i64> = HashSet::with_capacity(sorted.len());
+    for &value in sorted {
+        let complement = i128::from(target_value) - i128::from(value);
+        // A complement outside the `i64` range can't be one of the amounts
+        if let Some(complement) = i64::try_from(complement)
+            .ok()
+            .filter(|complement| seen.contains(complement))
+        {
+            return Ok(vec![complement, value]);
+        }
+        seen.insert(value);
+    }
+    Err(String::from("No pairs found that match the target value!"))
+}
+
+fn find_triple_two_pointer(target_value: i64, sorted: &[i64]) -> Result<Vec<i64>, String> {
+    for (i, &first) in sorted.iter().enumerate() {
+        let remaining = i128::from(target_value) - i128::from(first);
+        let (mut low, mut high) = (i + 1, sorted.len().saturating_sub(1));
+        while low < high {
+            let pair_sum = i128::from(sorted[low]) + i128::from(sorted[high]);
+            if pair_sum == remaining {
+                return Ok(vec![first, sorted[low], sorted[high]]);
+            } else if pair_sum < remaining {
+                low += 1;
+            } else {
+                high -= 1;
+            }
+        }
+    }
+    Err(String::from(
+        "No triples found that match the target value!",
+    ))
+}
+
+// Splits each k-combination of indices into its lowest k/2 indices and the rest.  All the
+// low halves are bucketed by sum, then every high half looks up its complement, keeping only
+// low halves whose last index comes before the high half's first index so that each
+// combination is found exactly once and no line is used twice.
+fn find_combination_mitm(target_value: i64, sorted: &[i64], k: usize) -> Result<Vec<i64>, String> {
+    let low_size = k / 2;
+    let high_size = k - low_size;
+    let sum_of = |indices: &[usize]| indices.iter().map(|&i| i128::from(sorted[i])).sum::<i128>();
+    let mut low_halves: HashMap<i128, Vec<Vec<usize>>> = HashMap::new();
+    for indices in (0..sorted.len()).combinations(low_size) {
+        low_halves
+            .entry(sum_of(&indices))
+            .or_default()
+            .push(indices);
+    }
+    for indices in (0..sorted.len()).combinations(high_size) {
+        let complement = i128::from(target_value) - sum_of(&indices);
+        if let Some(candidates) = low_halves.get(&complement) {
+            let first_high = indices.first().copied().unwrap_or(usize::MAX);
+            if let Some(low) = candidates
+                .iter()
+                .find(|low| low.last().map_or(true, |&last| last < first_high))
+            {
+                return Ok(low
+                    .iter()
+                    .chain(indices.iter())
+                    .map(|&i| sorted[i])
+                    .collect());
+            }
+        }
+    }
+    Err(String::from(
+        "No combinations found that match the target value!",
+    ))
+}
+
+fn find_k_sum(target_value: i64, sorted: &[i64], k: usize) -> Result<Vec<i64>, String> {
+    match k {
+        2 => find_pair_hashset(target_value, sorted),
+        3 => find_triple_two_pointer(target_value, sorted),
+        _ => find_combination_mitm(target_value, sorted, k),
+    }
+}
+
+/// A currency amount stored as a whole number of cents, so sums are exact.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Money(i64);
+
+impl From<u32> for Money {
+    fn from(units: u32) -> Self {
+        Money(i64::from(units) * 100)
+    }
+}
+
+impl From<Money> for i64 {
+    fn from(amount: Money) -> Self {
+        amount.0
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let trimmed = raw.trim();
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (units_str, cents_str) = match unsigned.split_once('.') {
+            Some((units, cents)) => (units, cents),
+            None => (unsigned, ""),
+        };
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if units_str.is_empty()
+            || !all_digits(units_str)
+            || !all_digits(cents_str)
+            || cents_str.len() > 2
+        {
+            return Err(format!("Couldn't parse amount '{}'", raw));
+        }
+        let units = units_str
+            .parse::<i64>()
+            .map_err(|_| format!("Amount '{}' is out of range", raw))?;
+        // "12.3" means 12.30, not 12.03
+        let cents = match cents_str.len() {
+            0 => 0,
+            1 => cents_str.parse::<i64>().unwrap() * 10,
+            _ => cents_str.parse::<i64>().unwrap(),
+        };
+        let total = units
+            .checked_mul(100)
+            .and_then(|value| value.checked_add(cents))
+            .ok_or_else(|| format!("Amount '{}' is out of range", raw))?;
+        Ok(Money(if negative { -total } else { total }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let magnitude = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, magnitude / 100, magnitude % 100)
+    }
+}
+
+/// The exact product of a set of amounts.  Multiplying k amounts of cents gives a number with
+/// 2k decimal places, so that's what we keep instead of rounding back to cents.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MoneyProduct {
+    value: i128,
+    decimal_places: u32,
+}
+
+impl MoneyProduct {
+    /// Overflow policy: the product is computed exactly in an `i128` and fails with an error
+    /// if it doesn't fit, rather than wrapping or saturating to a wrong answer.  That includes
+    /// having too many decimal places to display, which happens from 20 amounts upwards.
+    pub fn checked_product(amounts: &[Money]) -> Result<Self, String> {
+        let decimal_places = 2 * amounts.len() as u32;
+        if 10u128.checked_pow(decimal_places).is_none() {
+            return Err(format!(
+                "Product of {} amounts has too many decimal places",
+                amounts.len()
+            ));
+        }
+        let mut value: i128 = 1;
+        for amount in amounts {
+            value = value
+                .checked_mul(i128::from(amount.0))
+                .ok_or_else(|| format!("Product of {} overflows", amounts.iter().join(" * ")))?;
+        }
+        Ok(MoneyProduct {
+            value,
+            decimal_places,
+        })
+    }
+}
+
+impl fmt::Display for MoneyProduct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let magnitude = self.value.unsigned_abs();
+        let scale = 10u128.pow(self.decimal_places);
+        let fraction = format!(
+            "{:0width$}",
+            magnitude % scale,
+            width = self.decimal_places as usize
+        );
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, magnitude / scale)
+        } else {
+            write!(f, "{}{}.{}", sign, magnitude / scale, fraction)
+        }
+    }
+}
+
+/// Finds k ledger entries that add up to `target`.  Works with negative amounts.
+pub fn find_money_combination(
+    target: Money,
+    data: &[Money],
+    k: usize,
+) -> Result<Vec<Money>, String> {
+    let combination = find_k_sum(target.0, &sorted_amounts(data), k)?;
+    Ok(combination.into_iter().map(Money).collect())
+}
+
+/// Iterator over every distinct k-combination of values whose sum falls in a range of targets,
+/// yielded as `(sum, values)` with the values in ascending order.  Each line of input is used
+/// at most once, so a value only repeats in a combination if it appears on several lines.
+pub struct MatchingCombinations {
+    sorted: Vec<u32>,
+    k: usize,
+    targets: RangeInclusive<u32>,
+    chosen: Vec<usize>,
+    sum: u64,
+    done: bool,
+}
+
+impl MatchingCombinations {
+    pub fn new(data: &[u32], k: usize, targets: RangeInclusive<u32>) -> Self {
+        MatchingCombinations {
+            sorted: sorted_copy(data),
+            k,
+            targets,
+            chosen: Vec::with_capacity(k),
+            sum: 0,
+            done: false,
+        }
+    }
+
+    // Pushes `index` if the combination can still land in range: the data is sorted, so every
+    // value picked after it will be at least as big.
+    fn try_push(&mut self, index: usize) -> bool {
+        let remaining = self.k - self.chosen.len();
+        if index + remaining > self.sorted.len() {
+            return false;
+        }
+        let smallest_total = self.sum + u64::from(self.sorted[index]) * remaining as u64;
+        if smallest_total > u64::from(*self.targets.end()) {
+            return false;
+        }
+        self.chosen.push(index);
+        self.sum += u64::from(self.sorted[index]);
+        true
+    }
+
+    // Moves to the next candidate in depth-first order, returning false once exhausted.
+    fn advance(&mut self) -> bool {
+        if self.chosen.len() < self.k {
+            let start = self.chosen.last().map_or(0, |&last| last + 1);
+            if self.try_push(start) {
+                return true;
+            }
+        }
+        while let Some(last) = self.chosen.pop() {
+            self.sum -= u64::from(self.sorted[last]);
+            // Skip values equal to the one just tried at this depth so each combination of
+            // values is only produced once
+            let mut next = last + 1;
+            while next < self.sorted.len() && self.sorted[next] == self.sorted[last] {
+                next += 1;
+            }
+            if next < self.sorted.len() && self.try_push(next) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for MatchingCombinations {
+    type Item = (u32, Vec<u32>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if !self.advance() {
+                self.done = true;
+                break;
+            }
+            if self.chosen.len() == self.k && self.targets.contains(&(self.sum as u32)) {
+                let values = self.chosen.iter().map(|&i| self.sorted[i]).collect();
+                return Some((self.sum as u32, values));
+            }
+        }
+        None
+    }
+}
+
+/// Every distinct k-combination that sums to `target_value`.
+pub fn all_combinations(
+    target_value: u32,
+    data: &[u32],
+    k: usize,
+) -> impl Iterator<Item = Vec<u32>> {
+    MatchingCombinations::new(data, k, target_value..=target_value).map(|(_, values)| values)
+}
+
+/// Answers a whole range of targets with a single enumeration of the data.  Targets without any
+/// matching combination are left out of the map.
+pub fn combinations_by_target(
+    data: &[u32],
+    k: usize,
+    targets: RangeInclusive<u32>,
+) -> BTreeMap<u32, Vec<Vec<u32>>> {
+    let mut by_target: BTreeMap<u32, Vec<Vec<u32>>> = BTreeMap::new();
+    for (sum, values) in MatchingCombinations::new(data, k, targets) {
+        by_target.entry(sum).or_default().push(values);
+    }
+    by_target
+}
+
+#[derive(Debug, Clone)]
+pub struct SubsetSolution {
+    lines: Vec<usize>,
+    values: Vec<u32>,
+}
+
+impl SubsetSolution {
+    /// The product of the chosen entries, or an error if it doesn't fit in a `u64`.
+    pub fn product(&self) -> Result<u64, String> {
+        self.values
+            .iter()
+            .try_fold(1u64, |product, &value| {
+                product.checked_mul(u64::from(value))
+            })
+            .ok_or_else(|| format!("Product of {} overflows", self.values.iter().join(" * ")))
+    }
+}
+
+impl fmt::Display for SubsetSolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.product() {
+            Ok(product) => write!(f, "{}", product)?,
+            Err(_) => write!(f, "product overflows")?,
+        }
+        write!(f, " (lines {})", self.lines.iter().join(", "))
+    }
+}
+
+/// Finds the smallest subset of entries, of any size, that sums to `target_value`.
+///
+/// This is a 0/1 knapsack over the sums `0..=target_value` that minimises the number of
+/// entries used.  For each entry we keep a bitmap of the sums it improved, which is enough to
+/// walk back from the target and recover which lines were picked.
+pub fn find_smallest_subset(target_value: u32, data: &[u32]) -> Result<SubsetSolution, String> {
+    let target = target_value as usize;
+    let words = target / 64 + 1;
+    let mut fewest: Vec<Option<usize>> = vec![None; target + 1];
+    fewest[0] = Some(0);
+    let mut improved: Vec<Vec<u64>> = Vec::with_capacity(data.len());
+    for &value in data {
+        let value = value as usize;
+        let mut improved_here = vec![0u64; words];
+        if value > 0 {
+            for sum in (value..=target).rev() {
+                if let Some(count) = fewest[sum - value] {
+                    if fewest[sum].map_or(true, |current| count + 1 < current) {
+                        fewest[sum] = Some(count + 1);
+                        improved_here[sum / 64] |= 1 << (sum % 64);
+                    }
+                }
+            }
+        }
+        improved.push(improved_here);
+    }
+    if fewest[target].is_none() {
+        return Err(format!("No subset of entries sums to {}", target_value));
+    }
+
+    let mut lines = Vec::new();
+    let mut values = Vec::new();
+    let mut remaining = target;
+    for (i, improved_here) in improved.iter().enumerate().rev() {
+        if remaining == 0 {
+            break;
+        }
+        if improved_here[remaining / 64] & (1 << (remaining % 64)) != 0 {
+            lines.push(i + 1);
+            values.push(data[i]);
+            remaining -= data[i] as usize;
+        }
+    }
+    lines.reverse();
+    values.reverse();
+    Ok(SubsetSolution { lines, values })
+}
 
 pub fn generator(input: &str) -> Vec<u32> {
     input.lines().map(|line| line.parse::<u32>()).collect::<Result<Vec<u32>, ParseIntError>>().expect("Error parsing input for day 1")
 }
 
+// Only one generator can be registered per day, so ledgers with signs and cents are parsed by
+// the part that uses them
+pub fn money_generator(input: &str) -> Result<Vec<Money>, String> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            line.parse::<Money>()
+                .map_err(|e| format!("Line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
 pub fn part_one(data: &[u32]) -> u32 {
     let part_one_combination =
         find_combination(2020, &data, 2).expect("Couldn't find a solution for part one!");
     part_one_combination.iter().product()
 }
 
+pub fn part_one_hashset(data: &[u32]) -> i64 {
+    let part_one_combination = find_pair_hashset(2020, &sorted_amounts(data))
+        .expect("Couldn't find a solution for part one!");
+    part_one_combination.iter().product()
+}
+
 pub fn part_two(data: &[u32]) -> u32 {
     let part_two_combination =
         find_combination(2020, &data, 3).expect("Couldn't find a solution for part two!");
     part_two_combination.iter().product()
-}
\ No newline at end of file
+}
+
+pub fn part_two_two_pointer(data: &[u32]) -> i64 {
+    let part_two_combination = find_triple_two_pointer(2020, &sorted_amounts(data))
+        .expect("Couldn't find a solution for part two!");
+    part_two_combination.iter().product()
+}
+
+pub fn part_two_mitm(data: &[u32]) -> i64 {
+    let part_two_combination = find_combination_mitm(2020, &sorted_amounts(data), 3)
+        .expect("Couldn't find a solution for part two!");
+    part_two_combination.iter().product()
+}
+
+pub fn part_two_all(data: &[u32]) -> String {
+    all_combinations(2020, data, 3)
+        .map(|combination| combination.iter().product::<u32>().to_string())
+        .join(", ")
+}
+
+pub fn part_two_every_year(data: &[u32]) -> usize {
+    combinations_by_target(data, 3, 2000..=2100).len()
+}
+
+pub fn part_subset_sum(data: &[u32]) -> Result<SubsetSolution, String> {
+    let solution = find_smallest_subset(2020, data)?;
+    solution.product()?;
+    Ok(solution)
+}
+
+/// Part two over the ledger in the file named by `DAY01_LEDGER`, which can have negative and
+/// fractional amounts, or over the puzzle input if it isn't set.
+pub fn part_two_money(data: &[u32]) -> Result<MoneyProduct, String> {
+    let ledger: Vec<Money> = match std::env::var("DAY01_LEDGER") {
+        Ok(ledger_path) => {
+            let input = std::fs::read_to_string(&ledger_path)
+                .map_err(|e| format!("Couldn't read {}: {}", ledger_path, e))?;
+            money_generator(&input)?
+        }
+        Err(_) => data.iter().map(|&units| Money::from(units)).collect(),
+    };
+    let combination = find_money_combination(Money::from(2020), &ledger, 3)?;
+    MoneyProduct::checked_product(&combination)
+}
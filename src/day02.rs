@@ -1,37 +1,526 @@
+use itertools::Itertools;
+use memmap2::Mmap;
 use parse_display::{Display as PDisplay, FromStr as PFromStr};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const DEFAULT_POLICY: &str = "count";
+
+/// Optional policy name at the start of a line.  Displays with a trailing space so that lines
+/// without one round-trip unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyName(Option<String>);
+
+impl fmt::Display for PolicyName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some(name) => write!(f, "{} ", name),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PolicyName {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if name.is_empty() {
+            Ok(PolicyName(None))
+        } else {
+            Ok(PolicyName(Some(name.to_string())))
+        }
+    }
+}
 
 #[derive(PDisplay, PFromStr, Debug)]
-#[display("{low}-{high} {char}: {pass}")]
+#[display("{policy}{low}-{high} {char}: {pass}")]
+#[from_str(
+    regex = r"^(?:(?P<policy>[a-z][a-z0-9-]*) )?(?P<low>\d+)-(?P<high>\d+) (?P<char>.): (?P<pass>.*)$"
+)]
 pub struct Rule {
+    policy: PolicyName,
     low: usize,
     high: usize,
     char: char,
     pass: String,
 }
 
-pub fn generator(input: &str) -> Vec<Rule> {
-    input.lines().map(|line| line.parse::<Rule>()).collect::<Result<Vec<Rule>, _>>().expect("Error parsing input for day 2!")
+/// A way of checking a password against the numbers and character on its line.  Each policy
+/// decides for itself what `low`, `high` and `char` mean.
+pub trait PasswordPolicy {
+    fn name(&self) -> &str;
+
+    /// Explains why the password fails the policy, if it does.
+    fn check(&self, rule: &Rule) -> Result<(), String>;
+
+    fn is_valid(&self, rule: &Rule) -> bool {
+        self.check(rule).is_ok()
+    }
 }
 
-pub fn part_one(data: &[Rule]) -> usize {
-    let mut num_valid = 0;
-    for rule in data {
+/// Part one: `char` must appear between `low` and `high` times.
+pub struct CountPolicy;
+
+impl PasswordPolicy for CountPolicy {
+    fn name(&self) -> &str {
+        "count"
+    }
+
+    fn check(&self, rule: &Rule) -> Result<(), String> {
         let num_in_pass = rule.pass.matches(rule.char).count();
         if (rule.low <= num_in_pass) && (num_in_pass <= rule.high) {
-            num_valid += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "count {} not in {}-{}",
+                num_in_pass, rule.low, rule.high
+            ))
+        }
+    }
+}
+
+/// Part two: `char` must be at exactly one of the 1-indexed positions `low` and `high`.  A
+/// position outside the password fails the policy rather than panicking.
+pub struct PositionPolicy;
+
+impl PasswordPolicy for PositionPolicy {
+    fn name(&self) -> &str {
+        "position"
+    }
+
+    fn check(&self, rule: &Rule) -> Result<(), String> {
+        let char_at = |position: usize| {
+            position
+                .checked_sub(1)
+                .and_then(|index| rule.pass.chars().nth(index))
+                .ok_or_else(|| format!("position {} out of range", position))
+        };
+        let low_matches = char_at(rule.low)? == rule.char;
+        let high_matches = char_at(rule.high)? == rule.char;
+        match (low_matches, high_matches) {
+            (true, false) | (false, true) => Ok(()),
+            (true, true) => Err(format!(
+                "'{}' at both positions {} and {}",
+                rule.char, rule.low, rule.high
+            )),
+            (false, false) => Err(format!(
+                "'{}' at neither position {} nor {}",
+                rule.char, rule.low, rule.high
+            )),
         }
     }
-    num_valid
+}
+
+/// The password must match a regex.  The numbers and character on the line are ignored.
+pub struct RegexPolicy {
+    name: String,
+    regex: Regex,
+}
+
+impl RegexPolicy {
+    pub fn new(name: &str, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(RegexPolicy {
+            name: name.to_string(),
+            regex: Regex::new(pattern)?,
+        })
+    }
+}
+
+impl PasswordPolicy for RegexPolicy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, rule: &Rule) -> Result<(), String> {
+        if self.regex.is_match(&rule.pass) {
+            Ok(())
+        } else {
+            Err(format!("doesn't match /{}/", self.regex))
+        }
+    }
+}
+
+/// The password must draw on between `low` and `high` of the character classes lowercase,
+/// uppercase, digit and other.
+pub struct CharacterClassPolicy;
+
+impl PasswordPolicy for CharacterClassPolicy {
+    fn name(&self) -> &str {
+        "classes"
+    }
+
+    fn check(&self, rule: &Rule) -> Result<(), String> {
+        let mut classes = [false; 4];
+        for c in rule.pass.chars() {
+            let class = if c.is_lowercase() {
+                0
+            } else if c.is_uppercase() {
+                1
+            } else if c.is_ascii_digit() {
+                2
+            } else {
+                3
+            };
+            classes[class] = true;
+        }
+        let num_classes = classes.iter().filter(|&&present| present).count();
+        if (rule.low <= num_classes) && (num_classes <= rule.high) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} character classes not in {}-{}",
+                num_classes, rule.low, rule.high
+            ))
+        }
+    }
+}
+
+/// No character may repeat more than `high` times in a row.
+pub struct MaxRunPolicy;
+
+impl PasswordPolicy for MaxRunPolicy {
+    fn name(&self) -> &str {
+        "max-run"
+    }
+
+    fn check(&self, rule: &Rule) -> Result<(), String> {
+        let mut longest_run = 0;
+        let mut current_run = 0;
+        let mut previous = None;
+        for c in rule.pass.chars() {
+            current_run = if previous == Some(c) {
+                current_run + 1
+            } else {
+                1
+            };
+            longest_run = longest_run.max(current_run);
+            previous = Some(c);
+        }
+        // The reason goes in shareable reports, so it mustn't say which character repeats
+        if longest_run <= rule.high {
+            Ok(())
+        } else {
+            Err(format!("run of {} longer than {}", longest_run, rule.high))
+        }
+    }
+}
+
+/// Policies by name.  `PolicyRegistry::default()` knows about all the built-in policies, and
+/// more can be added with `register`.
+pub struct PolicyRegistry {
+    policies: HashMap<String, Box<dyn PasswordPolicy>>,
+}
+
+impl PolicyRegistry {
+    pub fn empty() -> Self {
+        PolicyRegistry {
+            policies: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, policy: Box<dyn PasswordPolicy>) {
+        self.policies.insert(policy.name().to_string(), policy);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn PasswordPolicy> {
+        self.policies.get(name).map(|policy| policy.as_ref())
+    }
+
+    /// Every registered policy, sorted by name so that reports come out in a stable order.
+    pub fn policies(&self) -> Vec<&dyn PasswordPolicy> {
+        let mut policies: Vec<&dyn PasswordPolicy> = self
+            .policies
+            .values()
+            .map(|policy| policy.as_ref())
+            .collect();
+        policies.sort_by(|a, b| a.name().cmp(b.name()));
+        policies
+    }
+
+    /// The policy named on the rule's line, or part one's count policy if it doesn't name one.
+    pub fn policy_for(&self, rule: &Rule) -> Result<&dyn PasswordPolicy, String> {
+        let name = rule.policy.0.as_deref().unwrap_or(DEFAULT_POLICY);
+        self.get(name)
+            .ok_or_else(|| format!("Unknown password policy '{}'", name))
+    }
+}
+
+impl Default for PolicyRegistry {
+    fn default() -> Self {
+        let mut registry = PolicyRegistry::empty();
+        registry.register(Box::new(CountPolicy));
+        registry.register(Box::new(PositionPolicy));
+        registry.register(Box::new(CharacterClassPolicy));
+        registry.register(Box::new(MaxRunPolicy));
+        registry.register(Box::new(
+            RegexPolicy::new("lowercase", r"^[a-z]+$").unwrap(),
+        ));
+        registry
+    }
+}
+
+/// The outcome of checking one line against one policy.  The password itself is left out so
+/// reports are safe to share.
+#[derive(Debug, Serialize)]
+pub struct Verdict {
+    line: usize,
+    policy: String,
+    valid: bool,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    /// Picks JSON for `.json` files and CSV for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => ReportFormat::Json,
+            _ => ReportFormat::Csv,
+        }
+    }
+}
+
+/// Checks every line under every policy in the registry, regardless of the policy the line
+/// names.
+pub fn audit(data: &[Rule], registry: &PolicyRegistry) -> Vec<Verdict> {
+    let policies = registry.policies();
+    let mut verdicts = Vec::with_capacity(data.len() * policies.len());
+    for (i, rule) in data.iter().enumerate() {
+        for policy in &policies {
+            let result = policy.check(rule);
+            verdicts.push(Verdict {
+                line: i + 1,
+                policy: policy.name().to_string(),
+                valid: result.is_ok(),
+                reason: result.err(),
+            });
+        }
+    }
+    verdicts
+}
+
+pub fn write_report<W: Write>(
+    verdicts: &[Verdict],
+    format: ReportFormat,
+    writer: W,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for verdict in verdicts {
+                csv_writer.serialize(verdict)?;
+            }
+            csv_writer.flush()
+        }
+        ReportFormat::Json => {
+            let mut writer = writer;
+            serde_json::to_writer_pretty(&mut writer, verdicts)?;
+            writeln!(writer)
+        }
+    }
+}
+
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Valid password counts under part one's and part two's policies.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ValidCounts {
+    part_one: usize,
+    part_two: usize,
+}
+
+impl std::ops::Add for ValidCounts {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        ValidCounts {
+            part_one: self.part_one + other.part_one,
+            part_two: self.part_two + other.part_two,
+        }
+    }
+}
+
+impl std::iter::Sum for ValidCounts {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(ValidCounts::default(), |total, counts| total + counts)
+    }
+}
+
+impl fmt::Display for ValidCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} / {}", self.part_one, self.part_two)
+    }
+}
+
+/// A rule line that borrows its password from the input instead of owning a `String`.
+struct RuleBytes<'a> {
+    low: usize,
+    high: usize,
+    char: u8,
+    pass: &'a [u8],
+}
+
+fn parse_number(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    bytes.iter().try_fold(0usize, |total, &digit| {
+        total
+            .checked_mul(10)?
+            .checked_add(usize::from(digit - b'0'))
+    })
+}
+
+// Same format as `Rule`, including the optional policy name, which is skipped since the
+// counts are always for the count and position policies.
+fn parse_rule_bytes(line: &[u8]) -> Option<RuleBytes<'_>> {
+    let line = match line.first() {
+        Some(first) if first.is_ascii_lowercase() => {
+            let space = line.iter().position(|&b| b == b' ')?;
+            &line[space + 1..]
+        }
+        _ => line,
+    };
+    let dash = line.iter().position(|&b| b == b'-')?;
+    let space = dash + line[dash..].iter().position(|&b| b == b' ')?;
+    let rest = &line[space + 1..];
+    if rest.len() < 3 || &rest[1..3] != b": " {
+        return None;
+    }
+    Some(RuleBytes {
+        low: parse_number(&line[..dash])?,
+        high: parse_number(&line[dash + 1..space])?,
+        char: rest[0],
+        pass: &rest[3..],
+    })
+}
+
+fn validate_line(line: &[u8]) -> Result<ValidCounts, String> {
+    let invalid_line = || format!("Couldn't parse line '{}'", String::from_utf8_lossy(line));
+    // Positions count characters rather than bytes, so anything outside ASCII goes through the
+    // regular parser and policies to keep the counts identical
+    if !line.is_ascii() {
+        let rule = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| line.parse::<Rule>().ok())
+            .ok_or_else(invalid_line)?;
+        return Ok(ValidCounts {
+            part_one: usize::from(CountPolicy.is_valid(&rule)),
+            part_two: usize::from(PositionPolicy.is_valid(&rule)),
+        });
+    }
+    let rule = parse_rule_bytes(line).ok_or_else(invalid_line)?;
+    let num_in_pass = rule.pass.iter().filter(|&&b| b == rule.char).count();
+    let char_at = |position: usize| position.checked_sub(1).and_then(|i| rule.pass.get(i));
+    let part_two_valid = match (char_at(rule.low), char_at(rule.high)) {
+        (Some(&low_char), Some(&high_char)) => (low_char == rule.char) ^ (high_char == rule.char),
+        _ => false,
+    };
+    Ok(ValidCounts {
+        part_one: usize::from(rule.low <= num_in_pass && num_in_pass <= rule.high),
+        part_two: usize::from(part_two_valid),
+    })
+}
+
+/// Splits the input into pieces of roughly `chunk_size` bytes, each ending on a line boundary.
+pub fn split_chunks(input: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::with_capacity(input.len() / chunk_size.max(1) + 1);
+    let mut start = 0;
+    while start < input.len() {
+        let mut end = (start + chunk_size.max(1)).min(input.len());
+        end = match input[end..].iter().position(|&b| b == b'\n') {
+            Some(newline) => end + newline + 1,
+            None => input.len(),
+        };
+        chunks.push(&input[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+fn validate_chunk(chunk: &[u8]) -> Result<ValidCounts, String> {
+    chunk
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(validate_line)
+        .sum()
+}
+
+/// Counts valid passwords without building a `Vec<Rule>`, validating chunks of the input in
+/// parallel.  Gives the same answers as `part_one` and `part_two`.
+pub fn count_valid_parallel(input: &[u8]) -> Result<ValidCounts, String> {
+    split_chunks(input, CHUNK_SIZE)
+        .into_par_iter()
+        .map(validate_chunk)
+        .try_reduce(ValidCounts::default, |a, b| Ok(a + b))
+}
+
+/// Memory-maps a password database and runs `count_valid_parallel` over it.
+pub fn count_valid_file(path: &Path) -> Result<ValidCounts, String> {
+    let file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+    // Safety: the map is only read, and we assume nobody truncates the file while we do so
+    let map = unsafe { Mmap::map(&file) }
+        .map_err(|e| format!("Couldn't map {}: {}", path.display(), e))?;
+    count_valid_parallel(&map)
+}
+
+fn count_valid(data: &[Rule], policy: &dyn PasswordPolicy) -> usize {
+    data.iter().filter(|rule| policy.is_valid(rule)).count()
+}
+
+pub fn generator(input: &str) -> Vec<Rule> {
+    input.lines().map(|line| line.parse::<Rule>()).collect::<Result<Vec<Rule>, _>>().expect("Error parsing input for day 2!")
+}
+
+pub fn part_one(data: &[Rule]) -> usize {
+    count_valid(data, &CountPolicy)
 }
 
 pub fn part_two(data: &[Rule]) -> usize {
+    count_valid(data, &PositionPolicy)
+}
+
+pub fn part_named_policies(data: &[Rule]) -> Result<usize, String> {
+    let registry = PolicyRegistry::default();
     let mut num_valid = 0;
     for rule in data {
-        let low_char = rule.pass.chars().nth(rule.low - 1).unwrap();
-        let high_char = rule.pass.chars().nth(rule.high - 1).unwrap();
-        if (rule.char == low_char) ^ (rule.char == high_char) {
-            num_valid += 1
+        if registry.policy_for(rule)?.is_valid(rule) {
+            num_valid += 1;
         }
     }
-    num_valid
-}
\ No newline at end of file
+    Ok(num_valid)
+}
+
+/// Audits every line under every policy and writes the report to the path in `DAY02_REPORT`, if
+/// it's set.  Returns the number of failing verdicts either way.
+pub fn part_audit(data: &[Rule]) -> Result<usize, String> {
+    let verdicts = audit(data, &PolicyRegistry::default());
+    if let Ok(report_path) = std::env::var("DAY02_REPORT") {
+        let path = Path::new(&report_path);
+        let file = File::create(path)
+            .map_err(|e| format!("Couldn't create report {}: {}", report_path, e))?;
+        write_report(&verdicts, ReportFormat::from_path(path), file)
+            .map_err(|e| format!("Couldn't write report {}: {}", report_path, e))?;
+    }
+    Ok(verdicts.iter().filter(|verdict| !verdict.valid).count())
+}
+
+/// Validates the database named in `DAY02_DATABASE` in parallel.  Without it, re-renders the
+/// parsed input so the counts can be checked against `part_one` and `part_two`.
+pub fn part_parallel(data: &[Rule]) -> Result<ValidCounts, String> {
+    match std::env::var("DAY02_DATABASE") {
+        Ok(database_path) => count_valid_file(Path::new(&database_path)),
+        Err(_) => count_valid_parallel(data.iter().join("\n").as_bytes()),
+    }
+}
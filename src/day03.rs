@@ -1,4 +1,14 @@
+use itertools::Itertools;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::ops::AddAssign;
+use std::path::Path;
+use std::str::FromStr;
+
+const EXPLORE_MAX_RIGHT: usize = 30;
+const EXPLORE_MAX_DOWN: usize = 10;
+const PIXELS_PER_SQUARE: usize = 4;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Coord {
@@ -15,18 +25,92 @@ impl AddAssign for Coord {
     }
 }
 
-fn has_tree(treemap: &[Vec<u8>], loc: &Coord) -> Option<bool> {
-    if loc.y >= treemap.len() {
-        return None;
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "right {}, down {}", self.x, self.y)
     }
-    if treemap[loc.y][loc.x % treemap[0].len()] == b'#' {
-        Some(true)
-    } else {
-        Some(false)
+}
+
+/// Parses `right,down`, like `3,1`.
+impl FromStr for Coord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s
+            .split_once(',')
+            .ok_or_else(|| format!("'{}' should look like RIGHT,DOWN", s))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("Couldn't parse '{}': {}", s, e))
+        };
+        Ok(Coord {
+            x: parse(x)?,
+            y: parse(y)?,
+        })
     }
 }
 
-fn check_slope(treemap: &[Vec<u8>], slope: Coord) -> usize {
+/// The `right,down` pair in the environment variable `name`, or `default` if it isn't set.
+fn coord_var(name: &str, default: Coord) -> Result<Coord, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().map_err(|e| format!("{}: {}", name, e)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// The tree map with each row packed into a bitset, one bit per square.
+#[derive(Debug, Clone)]
+pub struct TreeMap {
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl TreeMap {
+    /// Every row has to be as wide as the first, since the map repeats at that width.
+    fn from_rows(rows: &[&[u8]]) -> Result<Self, String> {
+        let width = rows.first().map_or(0, |row| row.len());
+        if width == 0 && !rows.is_empty() {
+            return Err(String::from("Line 1 is empty"));
+        }
+        if let Some((y, row)) = rows.iter().enumerate().find(|(_, row)| row.len() != width) {
+            return Err(format!(
+                "Line {} is {} squares wide, but line 1 is {}",
+                y + 1,
+                row.len(),
+                width
+            ));
+        }
+        let words_per_row = (width + 63) / 64;
+        let mut bits = vec![0u64; words_per_row * rows.len()];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &square) in row.iter().enumerate() {
+                if square == b'#' {
+                    bits[y * words_per_row + x / 64] |= 1 << (x % 64);
+                }
+            }
+        }
+        Ok(TreeMap {
+            width,
+            height: rows.len(),
+            words_per_row,
+            bits,
+        })
+    }
+}
+
+fn has_tree(treemap: &TreeMap, loc: &Coord) -> Option<bool> {
+    if loc.y >= treemap.height {
+        return None;
+    }
+    let x = loc.x % treemap.width;
+    let word = treemap.bits[loc.y * treemap.words_per_row + x / 64];
+    Some(word & (1 << (x % 64)) != 0)
+}
+
+fn check_slope(treemap: &TreeMap, slope: Coord) -> usize {
     let mut num_trees = 0;
     let mut curpos = Coord { x: 0, y: 0 };
     while let Some(current_tree) = has_tree(treemap, &curpos) {
@@ -38,15 +122,237 @@ fn check_slope(treemap: &[Vec<u8>], slope: Coord) -> usize {
     num_trees
 }
 
-pub fn generator(input: &str) -> Vec<Vec<u8>> {
-    input.lines().map(|line| line.as_bytes().to_vec()).collect()
+/// Tree counts for every slope checked by `explore_slopes`, from safest to worst.
+#[derive(Debug, Clone)]
+pub struct SlopeRanking(Vec<(Coord, usize)>);
+
+impl SlopeRanking {
+    pub fn safest(&self) -> Option<(Coord, usize)> {
+        self.0.first().copied()
+    }
+
+    pub fn worst(&self) -> Option<(Coord, usize)> {
+        self.0.last().copied()
+    }
+}
+
+impl fmt::Display for SlopeRanking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.safest(), self.worst()) {
+            (Some((safest, safest_trees)), Some((worst, worst_trees))) => write!(
+                f,
+                "safest {} ({} trees), worst {} ({} trees)",
+                safest, safest_trees, worst, worst_trees
+            ),
+            _ => write!(f, "no slopes checked"),
+        }
+    }
+}
+
+/// Checks every slope moving 0 to `max_right` across and 1 to `max_down` down per step.  Ties
+/// are broken in favour of shallower, then narrower, slopes.
+pub fn explore_slopes(treemap: &TreeMap, max_right: usize, max_down: usize) -> SlopeRanking {
+    let mut rankings: Vec<(Coord, usize)> = (1..=max_down)
+        .cartesian_product(0..=max_right)
+        .map(|(y, x)| {
+            let slope = Coord { x, y };
+            (slope, check_slope(treemap, slope))
+        })
+        .collect();
+    rankings.sort_by_key(|(slope, trees)| (*trees, slope.y, slope.x));
+    SlopeRanking(rankings)
 }
 
-pub fn part_one(data: &[Vec<u8>]) -> usize {
-    check_slope(&data, Coord { x: 3, y: 1 })
+/// A route down the map and the number of trees hit along it.  `squares` are absolute
+/// positions, so `x` keeps growing past the edge of the map the way the puzzle tiles it.
+#[derive(Debug, Clone)]
+pub struct TreePath {
+    squares: Vec<Coord>,
+    trees: usize,
 }
 
-pub fn part_two(data: &[Vec<u8>]) -> usize {
+impl TreePath {
+    pub fn squares(&self) -> &[Coord] {
+        &self.squares
+    }
+
+    pub fn trees(&self) -> usize {
+        self.trees
+    }
+}
+
+impl fmt::Display for TreePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} trees over {} squares",
+            self.trees,
+            self.squares.len()
+        )
+    }
+}
+
+/// Finds the path from the top-left square that hits the fewest trees, where every step can
+/// be any of `moves`.  Like `check_slope`, a path ends once its next step would go past the
+/// bottom row.
+///
+/// Each move has to go down at least one row, so the map is a DAG and can be solved a row at a
+/// time.  The state is the row and the column modulo the map width; we keep the cheapest way
+/// into each state along with the state and move it came from to rebuild the path.
+pub fn find_least_trees_path(treemap: &TreeMap, moves: &[Coord]) -> Result<TreePath, String> {
+    if moves.is_empty() {
+        return Err(String::from("Need at least one move to find a path"));
+    }
+    if let Some(flat_move) = moves.iter().find(|step| step.y == 0) {
+        return Err(format!("Move ({}) never leaves its row", flat_move));
+    }
+    let width = treemap.width;
+    let tree_cost =
+        |y: usize, x: usize| usize::from(has_tree(treemap, &Coord { x, y }) == Some(true));
+    let mut best: Vec<Vec<Option<usize>>> = vec![vec![None; width]; treemap.height];
+    let mut came_from: Vec<Vec<Option<(usize, usize)>>> = vec![vec![None; width]; treemap.height];
+    best[0][0] = Some(tree_cost(0, 0));
+    // (trees, row, column) of the cheapest square to leave the map from
+    let mut best_exit: Option<(usize, usize, usize)> = None;
+    for y in 0..treemap.height {
+        for x in 0..width {
+            let trees = match best[y][x] {
+                Some(trees) => trees,
+                None => continue,
+            };
+            for (move_index, step) in moves.iter().enumerate() {
+                let (next_y, next_x) = (y + step.y, (x + step.x) % width);
+                if next_y >= treemap.height {
+                    if best_exit.map_or(true, |(exit_trees, _, _)| trees < exit_trees) {
+                        best_exit = Some((trees, y, x));
+                    }
+                    continue;
+                }
+                let next_trees = trees + tree_cost(next_y, next_x);
+                if best[next_y][next_x].map_or(true, |current| next_trees < current) {
+                    best[next_y][next_x] = Some(next_trees);
+                    came_from[next_y][next_x] = Some((x, move_index));
+                }
+            }
+        }
+    }
+
+    let (trees, mut y, mut x) = best_exit.ok_or_else(|| String::from("No path leaves the map"))?;
+    let mut steps: Vec<Coord> = Vec::new();
+    while let Some((previous_x, move_index)) = came_from[y][x] {
+        let step = moves[move_index];
+        steps.push(step);
+        y -= step.y;
+        x = previous_x;
+    }
+    let mut curpos = Coord { x: 0, y: 0 };
+    let mut squares = vec![curpos];
+    for step in steps.into_iter().rev() {
+        curpos += step;
+        squares.push(curpos);
+    }
+    Ok(TreePath { squares, trees })
+}
+
+/// The map tiled out as far right as a slope's path goes, with the path drawn on it: `X` where
+/// it hits a tree and `O` where it crosses an open square.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    rows: Vec<Vec<u8>>,
+}
+
+impl Overlay {
+    pub fn new(treemap: &TreeMap, slope: Coord) -> Self {
+        let mut path: Vec<Coord> = Vec::new();
+        let mut curpos = Coord { x: 0, y: 0 };
+        while has_tree(treemap, &curpos).is_some() {
+            path.push(curpos);
+            curpos += slope
+        }
+        let last_x = path.iter().map(|loc| loc.x).max().unwrap_or(0);
+        let tiled_width = (last_x / treemap.width + 1) * treemap.width;
+        let mut rows: Vec<Vec<u8>> = (0..treemap.height)
+            .map(|y| {
+                (0..tiled_width)
+                    .map(|x| match has_tree(treemap, &Coord { x, y }) {
+                        Some(true) => b'#',
+                        _ => b'.',
+                    })
+                    .collect()
+            })
+            .collect();
+        for loc in path {
+            let square = &mut rows[loc.y][loc.x];
+            *square = if *square == b'#' { b'X' } else { b'O' };
+        }
+        Overlay { rows }
+    }
+
+    pub fn trees_hit(&self) -> usize {
+        self.rows
+            .iter()
+            .flatten()
+            .filter(|&&square| square == b'X')
+            .count()
+    }
+
+    pub fn write_ascii<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for row in &self.rows {
+            writer.write_all(row)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Writes a binary PPM with each square drawn as a block of `PIXELS_PER_SQUARE` pixels.
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let width = self.rows.first().map_or(0, |row| row.len());
+        write!(
+            writer,
+            "P6\n{} {}\n255\n",
+            width * PIXELS_PER_SQUARE,
+            self.rows.len() * PIXELS_PER_SQUARE
+        )?;
+        for row in &self.rows {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&square| {
+                    let colour: [u8; 3] = match square {
+                        b'#' => [34, 139, 34],
+                        b'X' => [220, 20, 60],
+                        b'O' => [65, 105, 225],
+                        _ => [255, 255, 255],
+                    };
+                    colour.repeat(PIXELS_PER_SQUARE)
+                })
+                .collect();
+            for _ in 0..PIXELS_PER_SQUARE {
+                writer.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.rows {
+            writeln!(f, "{}", String::from_utf8_lossy(row))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn generator(input: &str) -> Result<TreeMap, String> {
+    let rows: Vec<&[u8]> = input.lines().map(|line| line.as_bytes()).collect();
+    TreeMap::from_rows(&rows)
+}
+
+pub fn part_one(data: &TreeMap) -> usize {
+    check_slope(data, Coord { x: 3, y: 1 })
+}
+
+pub fn part_two(data: &TreeMap) -> usize {
     let slopes = vec![
         Coord { x: 1, y: 1 },
         Coord { x: 3, y: 1 },
@@ -56,7 +362,62 @@ pub fn part_two(data: &[Vec<u8>]) -> usize {
     ];
     let trees_per_slope: Vec<usize> = slopes
         .iter()
-        .map(|slope| check_slope(&data, *slope))
+        .map(|slope| check_slope(data, *slope))
         .collect();
     trees_per_slope.iter().product()
 }
+
+/// Finds the least-trees path using the moves in `DAY03_MOVES`, written as space-separated
+/// `right,down` pairs like `1,1 2,1 3,1`, which is also the default.  Prints the moves taken.
+pub fn part_least_trees(data: &TreeMap) -> Result<String, String> {
+    let moves: Vec<Coord> = match std::env::var("DAY03_MOVES") {
+        Ok(moves) => moves
+            .split_whitespace()
+            .map(|step| step.parse())
+            .collect::<Result<_, String>>()
+            .map_err(|e| format!("DAY03_MOVES: {}", e))?,
+        Err(_) => (1..=3).map(|x| Coord { x, y: 1 }).collect(),
+    };
+    let path = find_least_trees_path(data, &moves)?;
+    let taken = path
+        .squares()
+        .windows(2)
+        .map(|pair| format!("{},{}", pair[1].x - pair[0].x, pair[1].y - pair[0].y))
+        .join(" ");
+    Ok(format!("{} trees: {}", path.trees(), taken))
+}
+
+/// Explores slopes up to the `right,down` bounds in `DAY03_EXPLORE`, or 30,10 by default.
+pub fn part_explore(data: &TreeMap) -> Result<SlopeRanking, String> {
+    let bounds = coord_var(
+        "DAY03_EXPLORE",
+        Coord {
+            x: EXPLORE_MAX_RIGHT,
+            y: EXPLORE_MAX_DOWN,
+        },
+    )?;
+    Ok(explore_slopes(data, bounds.x, bounds.y))
+}
+
+/// Draws the path for the `right,down` slope in `DAY03_SLOPE`, or part one's 3,1 by default,
+/// and writes it to the path in `DAY03_OVERLAY`, if it's set: a PPM image for `.ppm` files and
+/// ASCII otherwise.  Returns the number of trees hit either way.
+pub fn part_overlay(data: &TreeMap) -> Result<usize, String> {
+    let slope = coord_var("DAY03_SLOPE", Coord { x: 3, y: 1 })?;
+    if slope.y == 0 {
+        return Err(format!("Slope ({}) never leaves the top row", slope));
+    }
+    let overlay = Overlay::new(data, slope);
+    if let Ok(overlay_path) = std::env::var("DAY03_OVERLAY") {
+        let path = Path::new(&overlay_path);
+        let file = File::create(path)
+            .map_err(|e| format!("Couldn't create overlay {}: {}", overlay_path, e))?;
+        let writer = BufWriter::new(file);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ppm") => overlay.write_ppm(writer),
+            _ => overlay.write_ascii(writer),
+        }
+        .map_err(|e| format!("Couldn't write overlay {}: {}", overlay_path, e))?;
+    }
+    Ok(overlay.trees_hit())
+}
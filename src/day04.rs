@@ -1,84 +1,856 @@
 use hex::decode as hex_decode;
-use scan_fmt::parse::ScanError;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use std::str::FromStr;
 
-const REQUIRED_FIELDS: [&str; 7] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
+// The fields the puzzle knows about, which the parser warns about anything outside of
+const KNOWN_FIELDS: [&str; 8] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid", "cid"];
 
-fn matches_year_range(year_str: &str, low: usize, high: usize) -> bool {
-    if year_str.len() != 4 {
-        return false;
+const PART_ONE_SCHEMA: &str = "\
+byr required
+iyr required
+eyr required
+hgt required
+hcl required
+ecl required
+pid required
+cid optional
+";
+
+const PART_TWO_SCHEMA: &str = "\
+byr required year-range 1920-2002
+iyr required year-range 2010-2020
+eyr required year-range 2020-2030
+hgt required measurement cm:150-193 in:59-76
+hcl required hex-colour
+ecl required enum amb blu brn gry grn hzl oth
+pid required digits 9
+cid optional
+";
+
+lazy_static! {
+    static ref PART_ONE: Schema = PART_ONE_SCHEMA.parse().unwrap();
+    static ref PART_TWO: Schema = PART_TWO_SCHEMA.parse().unwrap();
+}
+
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let (low, high) = scan_fmt!(range, "{d}-{d}", usize, usize)
+        .map_err(|_| format!("Couldn't parse range '{}'", range))?;
+    Ok((low, high))
+}
+
+#[derive(Debug, Clone)]
+pub enum Validator {
+    /// Any value will do, as long as the field is there
+    Any,
+    /// A four-digit year between the two bounds, inclusive
+    YearRange(usize, usize),
+    /// A number followed by one of the units, each with its own inclusive bounds
+    Measurement(Vec<(String, usize, usize)>),
+    /// `#` followed by six hex digits
+    HexColour,
+    /// One of a fixed list of values
+    Enum(Vec<String>),
+    /// Exactly this many digits, leading zeroes included
+    Digits(usize),
+    /// Matches the regex, which should be anchored to match the whole value
+    Regex(Regex),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ViolationKind {
+    Missing,
+    Malformed,
+    OutOfRange,
+    BadUnit,
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ViolationKind::Missing => "missing",
+            ViolationKind::Malformed => "malformed",
+            ViolationKind::OutOfRange => "out of range",
+            ViolationKind::BadUnit => "bad unit",
+        };
+        write!(f, "{}", description)
     }
-    if let Ok(year_int) = year_str.parse::<usize>() {
-        low <= year_int && year_int <= high
+}
+
+fn check_bounds(number: usize, low: usize, high: usize) -> Result<(), ViolationKind> {
+    if (low..=high).contains(&number) {
+        Ok(())
     } else {
-        false
+        Err(ViolationKind::OutOfRange)
     }
 }
 
-fn matches_height(raw_height: &str) -> bool {
-    let height_len = raw_height.len();
-    let (height_str, height_suffix) = raw_height.split_at(height_len - 2);
-    if let Ok(height_int) = height_str.parse::<usize>() {
-        match height_suffix {
-            "cm" => (150..=193).contains(&height_int),
-            "in" => (59..=76).contains(&height_int),
-            _ => false,
+impl Validator {
+    fn check(&self, value: &str) -> Result<(), ViolationKind> {
+        let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        match self {
+            Validator::Any => Ok(()),
+            Validator::YearRange(low, high) => {
+                if value.len() != 4 || !all_digits(value) {
+                    return Err(ViolationKind::Malformed);
+                }
+                check_bounds(value.parse().unwrap(), *low, *high)
+            }
+            Validator::Measurement(units) => {
+                let unit_start = value
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(value.len());
+                let (number, unit) = value.split_at(unit_start);
+                let number: usize = number.parse().map_err(|_| ViolationKind::Malformed)?;
+                let (_, low, high) = units
+                    .iter()
+                    .find(|(known_unit, _, _)| known_unit == unit)
+                    .ok_or(ViolationKind::BadUnit)?;
+                check_bounds(number, *low, *high)
+            }
+            Validator::HexColour => {
+                if value.len() == 7 && value.starts_with('#') && hex_decode(&value[1..]).is_ok() {
+                    Ok(())
+                } else {
+                    Err(ViolationKind::Malformed)
+                }
+            }
+            Validator::Enum(options) => {
+                if options.iter().any(|option| option == value) {
+                    Ok(())
+                } else {
+                    Err(ViolationKind::OutOfRange)
+                }
+            }
+            Validator::Digits(length) => {
+                if value.len() == *length && all_digits(value) {
+                    Ok(())
+                } else {
+                    Err(ViolationKind::Malformed)
+                }
+            }
+            Validator::Regex(regex) => {
+                if regex.is_match(value) {
+                    Ok(())
+                } else {
+                    Err(ViolationKind::Malformed)
+                }
+            }
         }
-    } else {
-        false
     }
 }
 
-#[derive(Debug)]
-pub struct Passport(HashMap<String, String>);
-impl Passport {
-    fn part_one_valid(&self) -> bool {
-        REQUIRED_FIELDS.iter().all(|key| self.0.contains_key(*key))
-    }
-
-    fn part_two_valid(&self) -> bool {
-        REQUIRED_FIELDS.iter().all(|key| {
-            if let Some(value) = self.0.get(*key) {
-                match *key {
-                    "byr" => matches_year_range(&value, 1920, 2002),
-                    "iyr" => matches_year_range(&value, 2010, 2020),
-                    "eyr" => matches_year_range(&value, 2020, 2030),
-                    "hgt" => matches_height(&value),
-                    "hcl" => value.len() == 7 && hex_decode(&value[1..]).is_ok(),
-                    "ecl" => matches!(
-                        value.as_str(),
-                        "amb" | "blu" | "brn" | "gry" | "grn" | "hzl" | "oth"
-                    ),
-                    "pid" => value.len() == 9 && value.parse::<usize>().is_ok(),
-                    _ => true,
+impl FromStr for Validator {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut words = spec.split_whitespace();
+        let kind = words.next().unwrap_or("any");
+        let args: Vec<&str> = words.collect();
+        match (kind, args.as_slice()) {
+            ("any", []) => Ok(Validator::Any),
+            ("year-range", [range]) => {
+                let (low, high) = parse_range(range)?;
+                Ok(Validator::YearRange(low, high))
+            }
+            ("measurement", units) if !units.is_empty() => {
+                let units = units
+                    .iter()
+                    .map(|unit_range| {
+                        let (unit, range) = unit_range.split_once(':').ok_or_else(|| {
+                            format!("Expected unit:low-high, got '{}'", unit_range)
+                        })?;
+                        let (low, high) = parse_range(range)?;
+                        Ok((unit.to_string(), low, high))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(Validator::Measurement(units))
+            }
+            ("hex-colour", []) => Ok(Validator::HexColour),
+            ("enum", options) if !options.is_empty() => Ok(Validator::Enum(
+                options.iter().map(|option| option.to_string()).collect(),
+            )),
+            ("digits", [length]) => {
+                Ok(Validator::Digits(length.parse().map_err(|_| {
+                    format!("Couldn't parse digit count '{}'", length)
+                })?))
+            }
+            ("regex", _) => {
+                // The pattern is the rest of the line, spaces and all
+                let pattern = spec.trim_start()["regex".len()..].trim();
+                Ok(Validator::Regex(Regex::new(pattern).map_err(|e| {
+                    format!("Couldn't compile regex '{}': {}", pattern, e)
+                })?))
+            }
+            _ => Err(format!("Couldn't parse validator '{}'", spec)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldRule {
+    key: String,
+    required: bool,
+    validator: Validator,
+}
+
+/// A set of field rules, one per line: `<key> <required|optional> [<validator> <args>...]`.
+/// Blank lines and lines starting with `#` are skipped, and a field with no validator only
+/// has to be present.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    fields: Vec<FieldRule>,
+}
+
+impl Schema {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        read_to_string(path)
+            .map_err(|e| format!("Couldn't read schema {}: {}", path.display(), e))?
+            .parse()
+    }
+
+    /// Missing optional fields are fine, and fields the schema doesn't mention are ignored.
+    pub fn check(&self, passport: &Passport) -> Vec<Violation> {
+        self.fields
+            .iter()
+            .filter_map(|field| {
+                let result = match passport.get(&field.key) {
+                    Some(value) => field.validator.check(value),
+                    None if field.required => Err(ViolationKind::Missing),
+                    None => Ok(()),
+                };
+                result.err().map(|kind| Violation {
+                    field: field.key.clone(),
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the schema has a rule for `key`, required or not.
+    pub fn declares(&self, key: &str) -> bool {
+        self.fields.iter().any(|field| field.key == key)
+    }
+
+    fn is_valid(&self, passport: &Passport) -> bool {
+        self.check(passport).is_empty()
+    }
+
+    pub fn count_valid(&self, data: &[Passport]) -> usize {
+        data.iter().filter(|record| self.is_valid(record)).count()
+    }
+
+    /// Every passport that breaks at least one rule, along with what it broke.
+    pub fn report(&self, data: &[Passport]) -> Vec<PassportReport> {
+        data.iter()
+            .enumerate()
+            .filter_map(|(index, passport)| {
+                let violations = self.check(passport);
+                if violations.is_empty() {
+                    None
+                } else {
+                    Some(PassportReport {
+                        index,
+                        line: passport.line,
+                        violations,
+                    })
                 }
-            } else {
-                false
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Schema {
+    type Err = String;
+    fn from_str(schema: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for (i, line) in schema.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.splitn(3, char::is_whitespace);
+            let key = words.next().unwrap().to_string();
+            let required = match words.next() {
+                Some("required") => true,
+                Some("optional") => false,
+                _ => {
+                    return Err(format!(
+                        "Line {}: expected 'required' or 'optional' after '{}'",
+                        i + 1,
+                        key
+                    ))
+                }
+            };
+            let validator = words
+                .next()
+                .unwrap_or("")
+                .parse()
+                .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            fields.push(FieldRule {
+                key,
+                required,
+                validator,
+            });
+        }
+        Ok(Schema { fields })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    field: String,
+    kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.field, self.kind)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PassportReport {
+    index: usize,
+    line: usize,
+    violations: Vec<Violation>,
+}
+
+impl fmt::Display for PassportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "record {} (line {}): {}",
+            self.index,
+            self.line,
+            self.violations.iter().join(", ")
+        )
+    }
+}
+
+/// How often each field fails in each way, most common first.
+#[derive(Debug, Clone)]
+pub struct ViolationSummary(Vec<(String, ViolationKind, usize)>);
+
+impl ViolationSummary {
+    pub fn new(reports: &[PassportReport]) -> Self {
+        let mut counts: HashMap<(String, ViolationKind), usize> = HashMap::new();
+        for violation in reports.iter().flat_map(|report| &report.violations) {
+            *counts
+                .entry((violation.field.clone(), violation.kind))
+                .or_insert(0) += 1;
+        }
+        let mut summary: Vec<(String, ViolationKind, usize)> = counts
+            .into_iter()
+            .map(|((field, kind), count)| (field, kind, count))
+            .collect();
+        summary.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (&a.0, a.1).cmp(&(&b.0, b.1))));
+        ViolationSummary(summary)
+    }
+}
+
+impl fmt::Display for ViolationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.0
+                .iter()
+                .map(|(field, kind, count)| format!("{} {} x{}", field, kind, count))
+                .join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IssueKind {
+    Malformed,
+    DuplicateKey,
+    UnknownKey,
+}
+
+/// Where something is in the input, in the terms of the format it was read from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Position {
+    /// A line and character column, counting from 1
+    Text { line: usize, column: usize },
+    /// A CSV row's line and the cell within it, counting from 1
+    Cell { line: usize, cell: usize },
+    /// An object in a JSON array, counting from 1
+    Record(usize),
+    /// Somewhere on this line
+    Line(usize),
+}
+
+impl Position {
+    /// The line of input, for the formats that have lines.
+    fn line(self) -> Option<usize> {
+        match self {
+            Position::Text { line, .. } | Position::Cell { line, .. } | Position::Line(line) => {
+                Some(line)
             }
+            Position::Record(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Position::Text { line, column } => write!(f, "line {}, column {}", line, column),
+            Position::Cell { line, cell } => write!(f, "line {}, cell {}", line, cell),
+            Position::Record(index) => write!(f, "record {}", index),
+            Position::Line(line) => write!(f, "line {}", line),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseIssue {
+    kind: IssueKind,
+    entry: String,
+    position: Position,
+}
+
+impl fmt::Display for ParseIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self.kind {
+            IssueKind::Malformed => "malformed entry",
+            IssueKind::DuplicateKey => "duplicate key in",
+            IssueKind::UnknownKey => "unknown key in",
+        };
+        write!(f, "{}: {} '{}'", self.position, description, self.entry)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    key: String,
+    value: String,
+    position: Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct Passport {
+    // Kept in input order, duplicates and all, so the record can be written back out as it was
+    fields: Vec<Field>,
+    // Line of the input the record starts on, counting from 1
+    line: usize,
+    // Duplicate and unknown keys, which are kept on the passport rather than rejecting it
+    warnings: Vec<ParseIssue>,
+}
+
+/// Collects fields for one passport, noting problems the same way whichever format the
+/// passport came from.
+struct PassportBuilder {
+    fields: Vec<Field>,
+    issues: Vec<ParseIssue>,
+}
+
+impl PassportBuilder {
+    fn new() -> Self {
+        PassportBuilder {
+            fields: Vec::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    fn issue(&mut self, kind: IssueKind, entry: &str, position: Position) {
+        self.issues.push(ParseIssue {
+            kind,
+            entry: entry.to_string(),
+            position,
+        });
+    }
+
+    fn add(&mut self, key: &str, value: &str, position: Position) {
+        let entry = format!("{}:{}", key, value);
+        if self.fields.iter().any(|field| field.key == key) {
+            self.issue(IssueKind::DuplicateKey, &entry, position);
+        }
+        if !KNOWN_FIELDS.contains(&key) {
+            self.issue(IssueKind::UnknownKey, &entry, position);
+        }
+        self.fields.push(Field {
+            key: key.to_string(),
+            value: value.to_string(),
+            position,
+        });
+    }
+
+    /// Malformed entries are errors; duplicate and unknown keys are only warnings.
+    fn build(self, first_line: usize) -> Result<Passport, Vec<ParseIssue>> {
+        if self
+            .issues
+            .iter()
+            .any(|issue| issue.kind == IssueKind::Malformed)
+        {
+            return Err(self.issues);
+        }
+        Ok(Passport {
+            fields: self.fields,
+            line: first_line,
+            warnings: self.issues,
         })
     }
 }
+
+impl Passport {
+    /// Parses one record that starts on `first_line` of the input.
+    pub fn parse(record: &str, first_line: usize) -> Result<Self, Vec<ParseIssue>> {
+        let mut builder = PassportBuilder::new();
+        for (i, line) in record.lines().enumerate() {
+            for entry in line.split_whitespace() {
+                let position = Position::Text {
+                    line: first_line + i,
+                    column: entry.as_ptr() as usize - line.as_ptr() as usize + 1,
+                };
+                match entry.split_once(':') {
+                    Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+                        builder.add(key, value, position)
+                    }
+                    _ => builder.issue(IssueKind::Malformed, entry, position),
+                }
+            }
+        }
+        builder.build(first_line)
+    }
+
+    /// What strict mode rejects the passport for: any duplicate key, and any key `schema`
+    /// doesn't declare, at the positions they had in the input.
+    pub fn strict_issues(&self, schema: &Schema) -> Vec<ParseIssue> {
+        let duplicates = self
+            .warnings
+            .iter()
+            .filter(|issue| issue.kind == IssueKind::DuplicateKey)
+            .cloned();
+        let unknown = self
+            .fields
+            .iter()
+            .filter(|field| !schema.declares(&field.key))
+            .map(|field| ParseIssue {
+                kind: IssueKind::UnknownKey,
+                entry: format!("{}:{}", field.key, field.value),
+                position: field.position,
+            });
+        duplicates
+            .chain(unknown)
+            .sorted_by_key(|issue| issue.position)
+            .collect()
+    }
+
+    /// The value for `key`.  If the key is duplicated the last one wins, as it always has.
+    fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|field| field.key == key)
+            .map(|field| field.value.as_str())
+    }
+}
+
 impl FromStr for Passport {
-    type Err = ScanError;
+    type Err = String;
     fn from_str(record: &str) -> Result<Self, Self::Err> {
-        let mut new_passport = HashMap::new();
-        for entry in record.split_whitespace() {
-            let (key, value) = scan_fmt!(entry, "{}:{}", String, String)?;
-            new_passport.insert(key, value);
+        Passport::parse(record, 1).map_err(|issues| issues.iter().join("; "))
+    }
+}
+
+/// Writes the fields back out in their original order, keeping fields that shared a line on
+/// the same line.
+impl fmt::Display for Passport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (_, line_fields)) in self
+            .fields
+            .iter()
+            .group_by(|field| field.position.line())
+            .into_iter()
+            .enumerate()
+        {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let entries = line_fields.map(|field| format!("{}:{}", field.key, field.value));
+            write!(f, "{}", entries.format(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A batch of passports in the blank-line-separated format of the puzzle input.
+pub struct Batch<'a>(&'a [Passport]);
+
+impl fmt::Display for Batch<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, passport) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", passport)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses every record, collecting the issues from all of them rather than stopping at the
+/// first bad record.
+pub fn parse_batch(input: &str) -> Result<Vec<Passport>, Vec<ParseIssue>> {
+    let mut line = 1;
+    let mut passports = Vec::new();
+    let mut issues = Vec::new();
+    for record in input.split("\n\n") {
+        match Passport::parse(record, line) {
+            Ok(passport) => passports.push(passport),
+            Err(record_issues) => issues.extend(record_issues),
+        }
+        line += record.matches('\n').count() + 2;
+    }
+    if issues.is_empty() {
+        Ok(passports)
+    } else {
+        Err(issues)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BatchFormat {
+    /// Blank-line-separated `key:value` records, like the puzzle input
+    Records,
+    /// An array of objects, one per passport
+    Json,
+    /// A header row of keys, then one passport per row with empty cells for missing fields
+    Csv,
+}
+
+impl BatchFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => BatchFormat::Json,
+            Some("csv") => BatchFormat::Csv,
+            _ => BatchFormat::Records,
+        }
+    }
+
+    /// Guesses the format from the input itself, since aoc-main only hands us the contents.
+    pub fn detect(input: &str) -> Self {
+        let first_line = input.trim_start().lines().next().unwrap_or("");
+        if first_line.starts_with('[') {
+            BatchFormat::Json
+        } else if first_line.contains(',') && !first_line.contains(':') {
+            BatchFormat::Csv
+        } else {
+            BatchFormat::Records
+        }
+    }
+}
+
+// Passports from JSON don't have lines of their own, so they're numbered by position in the
+// array instead.
+fn parse_json_batch(input: &str) -> Result<Vec<Passport>, Vec<ParseIssue>> {
+    let json_error = |e: serde_json::Error| {
+        vec![ParseIssue {
+            kind: IssueKind::Malformed,
+            entry: e.to_string(),
+            position: Position::Text {
+                line: e.line(),
+                column: e.column(),
+            },
+        }]
+    };
+    let records: Vec<Map<String, Value>> = serde_json::from_str(input).map_err(json_error)?;
+    let mut passports = Vec::new();
+    let mut issues = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let mut builder = PassportBuilder::new();
+        let position = Position::Record(i + 1);
+        for (key, value) in record {
+            match value {
+                Value::String(value) if !value.is_empty() => builder.add(key, value, position),
+                Value::Number(value) => builder.add(key, &value.to_string(), position),
+                _ => builder.issue(
+                    IssueKind::Malformed,
+                    &format!("{}:{}", key, value),
+                    position,
+                ),
+            }
+        }
+        match builder.build(i + 1) {
+            Ok(passport) => passports.push(passport),
+            Err(record_issues) => issues.extend(record_issues),
+        }
+    }
+    if issues.is_empty() {
+        Ok(passports)
+    } else {
+        Err(issues)
+    }
+}
+
+fn parse_csv_batch(input: &str) -> Result<Vec<Passport>, Vec<ParseIssue>> {
+    let csv_error = |e: csv::Error| {
+        let line = e.position().map_or(0, |position| position.line() as usize);
+        vec![ParseIssue {
+            kind: IssueKind::Malformed,
+            entry: e.to_string(),
+            position: Position::Line(line),
+        }]
+    };
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let mut passports = Vec::new();
+    let mut issues = Vec::new();
+    for row in reader.records() {
+        let row = row.map_err(csv_error)?;
+        let line = row
+            .position()
+            .map_or(0, |position| position.line() as usize);
+        let mut builder = PassportBuilder::new();
+        for (cell, (key, value)) in headers.iter().zip(row.iter()).enumerate() {
+            let position = Position::Cell {
+                line,
+                cell: cell + 1,
+            };
+            if !value.is_empty() {
+                builder.add(key, value, position);
+            }
+        }
+        match builder.build(line) {
+            Ok(passport) => passports.push(passport),
+            Err(record_issues) => issues.extend(record_issues),
+        }
+    }
+    if issues.is_empty() {
+        Ok(passports)
+    } else {
+        Err(issues)
+    }
+}
+
+pub fn read_batch(input: &str, format: BatchFormat) -> Result<Vec<Passport>, Vec<ParseIssue>> {
+    match format {
+        BatchFormat::Records => parse_batch(input),
+        BatchFormat::Json => parse_json_batch(input),
+        BatchFormat::Csv => parse_csv_batch(input),
+    }
+}
+
+/// Writes a batch in any format.  JSON objects can't hold a key twice, so duplicated keys keep
+/// their last value there, the same one validation uses.
+pub fn write_batch<W: Write>(
+    passports: &[Passport],
+    format: BatchFormat,
+    mut writer: W,
+) -> Result<(), String> {
+    match format {
+        BatchFormat::Records => write!(writer, "{}", Batch(passports)).map_err(|e| e.to_string()),
+        BatchFormat::Json => {
+            let records: Vec<Map<String, Value>> = passports
+                .iter()
+                .map(|passport| {
+                    passport
+                        .fields
+                        .iter()
+                        .map(|field| (field.key.clone(), Value::String(field.value.clone())))
+                        .collect()
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut writer, &records).map_err(|e| e.to_string())?;
+            writeln!(writer).map_err(|e| e.to_string())
+        }
+        BatchFormat::Csv => {
+            // The usual fields first, then any others in the order they turn up
+            let mut keys: Vec<&str> = KNOWN_FIELDS.to_vec();
+            for field in passports.iter().flat_map(|passport| &passport.fields) {
+                if !keys.contains(&field.key.as_str()) {
+                    keys.push(&field.key);
+                }
+            }
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(&keys).map_err(|e| e.to_string())?;
+            for passport in passports {
+                let row = keys.iter().map(|key| passport.get(key).unwrap_or(""));
+                csv_writer.write_record(row).map_err(|e| e.to_string())?;
+            }
+            csv_writer.flush().map_err(|e| e.to_string())
         }
-        Ok(Passport(new_passport))
     }
 }
 
 pub fn generator(input: &str) -> Vec<Passport> {
-    input.split("\n\n").map(|record| record.parse()).collect::<Result<Vec<Passport>, _>>().expect("Error parsing input for day 4!")
+    read_batch(input, BatchFormat::detect(input))
+        .map_err(|issues| issues.iter().join("\n"))
+        .expect("Error parsing input for day 4!")
 }
 
 pub fn part_one(data: &[Passport]) -> usize {
-    data.iter().filter(|record| record.part_one_valid()).count()
+    PART_ONE.count_valid(data)
 }
 
 pub fn part_two(data: &[Passport]) -> usize {
-    data.iter().filter(|record| record.part_two_valid()).count()
+    PART_TWO.count_valid(data)
+}
+
+/// The schema file named in `DAY04_SCHEMA`, or part two's schema if it isn't set.
+fn chosen_schema() -> Result<Schema, String> {
+    match std::env::var("DAY04_SCHEMA") {
+        Ok(schema_path) => Schema::load(Path::new(&schema_path)),
+        Err(_) => Ok(PART_TWO.clone()),
+    }
+}
+
+/// Validates against the schema file named in `DAY04_SCHEMA`, or part two's schema if it
+/// isn't set.
+pub fn part_schema(data: &[Passport]) -> Result<usize, String> {
+    Ok(chosen_schema()?.count_valid(data))
+}
+
+/// Summarises which of part two's rules fail most often, and writes the per-passport report to
+/// the path in `DAY04_REPORT` if it's set.
+pub fn part_violations(data: &[Passport]) -> Result<ViolationSummary, String> {
+    let reports = PART_TWO.report(data);
+    if let Ok(report_path) = std::env::var("DAY04_REPORT") {
+        let mut writer = BufWriter::new(
+            File::create(&report_path)
+                .map_err(|e| format!("Couldn't create report {}: {}", report_path, e))?,
+        );
+        for report in &reports {
+            writeln!(writer, "{}", report)
+                .map_err(|e| format!("Couldn't write report {}: {}", report_path, e))?;
+        }
+    }
+    Ok(ViolationSummary::new(&reports))
+}
+
+/// Validates in strict mode, failing on any duplicate key or any key the schema doesn't
+/// declare.  The schema is the same one `part_schema` uses.
+pub fn part_strict(data: &[Passport]) -> Result<usize, String> {
+    let schema = chosen_schema()?;
+    let issues: Vec<ParseIssue> = data
+        .iter()
+        .flat_map(|passport| passport.strict_issues(&schema))
+        .collect();
+    if !issues.is_empty() {
+        return Err(issues.iter().join("; "));
+    }
+    Ok(schema.count_valid(data))
+}
+
+/// Writes the normalised batch to the path in `DAY04_NORMALISED`, if it's set, and returns the
+/// number of warnings the parser raised.  The output format follows the extension, so
+/// this also converts between formats.
+pub fn part_normalise(data: &[Passport]) -> Result<usize, String> {
+    if let Ok(normalised_path) = std::env::var("DAY04_NORMALISED") {
+        let path = Path::new(&normalised_path);
+        let file = File::create(path)
+            .map_err(|e| format!("Couldn't create {}: {}", normalised_path, e))?;
+        write_batch(data, BatchFormat::from_path(path), BufWriter::new(file))
+            .map_err(|e| format!("Couldn't write {}: {}", normalised_path, e))?;
+    }
+    Ok(data.iter().map(|passport| passport.warnings.len()).sum())
 }
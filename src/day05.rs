@@ -1,44 +1,339 @@
-use std::num::ParseIntError;
+use itertools::Itertools;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
 
 type Seat = u32;
+const SEAT_BITS: u32 = Seat::MAX.count_ones();
 
-fn translate_char(c: &char) -> char {
-    match c {
-        'F' => '0',
-        'B' => '1',
-        'L' => '0',
-        'R' => '1',
-        unknown => *unknown
+/// Bits needed to number `count` things from zero.
+fn bits_for(count: u32) -> u32 {
+    SEAT_BITS - (count - 1).leading_zeros()
+}
+
+/// Converts between boarding passes and seat IDs for a plane with any number of rows and
+/// columns.  A pass is the row in binary as F/B followed by the column in binary as L/R, each
+/// using as few bits as that count needs, and the seat ID is `row * columns + column`.  For the
+/// puzzle's 128x8 plane that's just all the bits read as one binary number.
+#[derive(Debug, Copy, Clone)]
+pub struct SeatCodec {
+    rows: u32,
+    columns: u32,
+    row_bits: u32,
+    column_bits: u32,
+}
+
+impl Default for SeatCodec {
+    fn default() -> Self {
+        SeatCodec {
+            rows: 128,
+            columns: 8,
+            row_bits: 7,
+            column_bits: 3,
+        }
+    }
+}
+
+impl SeatCodec {
+    pub fn new(rows: u32, columns: u32) -> Result<Self, String> {
+        if rows == 0 || columns == 0 {
+            return Err(format!("Plane of {}x{} seats has no seats", rows, columns));
+        }
+        let (row_bits, column_bits) = (bits_for(rows), bits_for(columns));
+        if rows.checked_mul(columns).is_none() || row_bits + column_bits >= SEAT_BITS {
+            return Err(format!("Plane of {}x{} seats is too big", rows, columns));
+        }
+        Ok(SeatCodec {
+            rows,
+            columns,
+            row_bits,
+            column_bits,
+        })
+    }
+
+    pub fn pass_len(&self) -> usize {
+        (self.row_bits + self.column_bits) as usize
+    }
+
+    pub fn seats(&self) -> Seat {
+        self.rows * self.columns
+    }
+
+    pub fn columns(&self) -> Seat {
+        self.columns
+    }
+
+    /// Decodes a pass straight from its bytes, rejecting anything that isn't F/B in the row part
+    /// or L/R in the column part, and any row or column the plane doesn't have.
+    pub fn decode(&self, pass: &str) -> Result<Seat, String> {
+        if pass.len() != self.pass_len() {
+            return Err(format!(
+                "Boarding pass {} should be {} characters long",
+                pass,
+                self.pass_len()
+            ));
+        }
+        let bits = pass.bytes().enumerate().try_fold(0, |bits, (i, c)| {
+            let bit = match (i < self.row_bits as usize, c) {
+                (true, b'F') | (false, b'L') => 0,
+                (true, b'B') | (false, b'R') => 1,
+                _ => {
+                    return Err(format!(
+                        "Unexpected {:?} at position {} of boarding pass {}",
+                        char::from(c),
+                        i + 1,
+                        pass
+                    ))
+                }
+            };
+            Ok(bits << 1 | bit)
+        })?;
+        let (row, column) = (
+            bits >> self.column_bits,
+            bits & ((1 << self.column_bits) - 1),
+        );
+        if row >= self.rows {
+            return Err(format!(
+                "Boarding pass {} is for row {}, but the plane has {} rows",
+                pass, row, self.rows
+            ));
+        }
+        if column >= self.columns {
+            return Err(format!(
+                "Boarding pass {} is for column {}, but the plane has {} columns",
+                pass, column, self.columns
+            ));
+        }
+        Ok(row * self.columns + column)
+    }
+
+    pub fn encode(&self, seat: Seat) -> Result<BoardingPass, String> {
+        if seat >= self.seats() {
+            return Err(format!(
+                "Seat {} doesn't exist on a plane with {} seats",
+                seat,
+                self.seats()
+            ));
+        }
+        Ok(BoardingPass { codec: *self, seat })
+    }
+}
+
+/// Parses a plane size like `128x8`.
+impl FromStr for SeatCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rows, columns) = s
+            .split_once('x')
+            .ok_or_else(|| format!("Plane size {} should look like ROWSxCOLUMNS", s))?;
+        let parse = |count: &str| {
+            count
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("Couldn't parse plane size {}: {}", s, e))
+        };
+        SeatCodec::new(parse(rows)?, parse(columns)?)
     }
 }
 
-fn translate_seat(seat: &str) -> Result<Seat, ParseIntError> {
-    let mut outstring = String::with_capacity(seat.len());
-    for c in seat.chars() {
-        outstring.push(translate_char(&c));
+/// The plane named in `DAY05_PLANE`, or the puzzle's 128x8 if it isn't set.
+fn plane() -> Result<SeatCodec, String> {
+    match std::env::var("DAY05_PLANE") {
+        Ok(size) => size.parse(),
+        Err(_) => Ok(SeatCodec::default()),
     }
-    Seat::from_str_radix(outstring.as_str(), 2)
 }
 
-pub fn generator(input: &str) -> (Seat, Seat, Seat) {
-    let data = input
-        .lines()
-        .map(translate_seat)
-        .collect::<Result<Vec<Seat>, _>>()
-        .expect("Error parsing input for day 5!");
-    data.iter()
-        .copied()
-        .fold((Seat::MAX, Seat::MIN, 0), |(min, max, sum), x| {
-            (min.min(x), max.max(x), sum + x)
+/// A seat ID that displays as its boarding pass.
+#[derive(Debug, Copy, Clone)]
+pub struct BoardingPass {
+    codec: SeatCodec,
+    seat: Seat,
+}
+
+impl fmt::Display for BoardingPass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (row, column) = (
+            self.seat / self.codec.columns,
+            self.seat % self.codec.columns,
+        );
+        for bit in (0..self.codec.row_bits).rev() {
+            write!(f, "{}", if row & (1 << bit) != 0 { 'B' } else { 'F' })?;
+        }
+        for bit in (0..self.codec.column_bits).rev() {
+            write!(f, "{}", if column & (1 << bit) != 0 { 'R' } else { 'L' })?;
+        }
+        Ok(())
+    }
+}
+
+/// Every seat on the plane and whether anyone has a boarding pass for it.
+#[derive(Debug, Clone)]
+pub struct SeatMap {
+    codec: SeatCodec,
+    occupied: Vec<bool>,
+}
+
+/// An empty seat, and whether the seats with the IDs either side of it are both taken.
+#[derive(Debug, Copy, Clone)]
+pub struct Vacancy {
+    seat: Seat,
+    between_occupied: bool,
+}
+
+impl SeatMap {
+    /// Decodes one pass per line.  Two passes for the same seat are an error, and every
+    /// duplicate is listed rather than just the first.
+    pub fn from_passes(codec: SeatCodec, input: &str) -> Result<Self, String> {
+        // The line each seat's pass was first seen on
+        let mut first_seen: Vec<Option<usize>> = vec![None; codec.seats() as usize];
+        let mut duplicates: Vec<String> = Vec::new();
+        for (i, pass) in input.lines().enumerate() {
+            let seat = codec
+                .decode(pass)
+                .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            match first_seen[seat as usize] {
+                Some(first_line) => duplicates.push(format!(
+                    "Seat {} ({}) is on lines {} and {}",
+                    seat,
+                    pass,
+                    first_line,
+                    i + 1
+                )),
+                None => first_seen[seat as usize] = Some(i + 1),
+            }
+        }
+        if !duplicates.is_empty() {
+            return Err(duplicates.join("\n"));
+        }
+        Ok(SeatMap {
+            codec,
+            occupied: first_seen.iter().map(Option::is_some).collect(),
         })
+    }
+
+    fn is_occupied(&self, seat: Seat) -> bool {
+        self.occupied.get(seat as usize).copied().unwrap_or(false)
+    }
+
+    fn between_occupied(&self, seat: Seat) -> bool {
+        seat > 0 && self.is_occupied(seat - 1) && self.is_occupied(seat + 1)
+    }
+
+    pub fn highest(&self) -> Option<Seat> {
+        self.occupied
+            .iter()
+            .rposition(|&taken| taken)
+            .map(|seat| seat as Seat)
+    }
+
+    pub fn vacancies(&self) -> Vec<Vacancy> {
+        (0..self.codec.seats())
+            .filter(|&seat| !self.is_occupied(seat))
+            .map(|seat| Vacancy {
+                seat,
+                between_occupied: self.between_occupied(seat),
+            })
+            .collect()
+    }
+
+    /// Draws one line per row, prefixed with the row number: `#` for a taken seat, `o` for an
+    /// empty seat between two taken ones and `.` for any other empty seat.
+    pub fn write_grid<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let columns = self.codec.columns() as usize;
+        let row_label_width = (self.occupied.len() / columns - 1).to_string().len();
+        for (row, seats) in self.occupied.chunks(columns).enumerate() {
+            write!(writer, "{:>width$} ", row, width = row_label_width)?;
+            for (column, &taken) in seats.iter().enumerate() {
+                let seat = (row * columns + column) as Seat;
+                let square = if taken {
+                    '#'
+                } else if self.between_occupied(seat) {
+                    'o'
+                } else {
+                    '.'
+                };
+                write!(writer, "{}", square)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
 }
 
-pub fn part_one(data: &(Seat, Seat, Seat)) -> u32 {
-    let (_, max, _) = *data;
-    max
+/// Lists empty seats as ranges of IDs, with a `*` on any seat between two taken ones.
+#[derive(Debug, Clone)]
+pub struct VacancyReport(Vec<Vacancy>);
+
+impl fmt::Display for VacancyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ranges: Vec<(Vacancy, Vacancy)> = Vec::new();
+        for &vacancy in &self.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if last.seat + 1 == vacancy.seat && !vacancy.between_occupied => {
+                    *last = vacancy
+                }
+                _ => ranges.push((vacancy, vacancy)),
+            }
+        }
+        let ranges = ranges.iter().map(|(first, last)| {
+            let star = if first.between_occupied { "*" } else { "" };
+            if first.seat == last.seat {
+                format!("{}{}", first.seat, star)
+            } else {
+                format!("{}-{}", first.seat, last.seat)
+            }
+        });
+        write!(f, "{} empty: {}", self.0.len(), ranges.format(", "))
+    }
+}
+
+pub fn generator(input: &str) -> Result<SeatMap, String> {
+    SeatMap::from_passes(plane()?, input)
+}
+
+pub fn part_one(data: &SeatMap) -> Result<Seat, String> {
+    data.highest()
+        .ok_or_else(|| String::from("No boarding passes"))
+}
+
+/// Our seat is the only empty one with both neighbours taken.
+pub fn part_two(data: &SeatMap) -> Result<Seat, String> {
+    let candidates: Vec<Seat> = data
+        .vacancies()
+        .iter()
+        .filter(|vacancy| vacancy.between_occupied)
+        .map(|vacancy| vacancy.seat)
+        .collect();
+    match candidates.as_slice() {
+        [seat] => Ok(*seat),
+        [] => Err(String::from("No empty seat between two taken ones")),
+        _ => Err(format!(
+            "Several empty seats between two taken ones: {}",
+            candidates.iter().join(", ")
+        )),
+    }
+}
+
+/// The boarding pass that part two's seat would have been printed on.
+pub fn part_two_pass(data: &SeatMap) -> Result<BoardingPass, String> {
+    plane()?.encode(part_two(data)?)
 }
 
-pub fn part_two(data: &(Seat, Seat, Seat)) -> u32 {
-    let (min, max, sum) = *data;
-    (min..=max).sum::<Seat>() - sum
+pub fn part_vacancies(data: &SeatMap) -> VacancyReport {
+    VacancyReport(data.vacancies())
+}
+
+/// Writes the seat grid to the path in `DAY05_MAP`, if it's set, and returns the number of
+/// empty seats.
+pub fn part_map(data: &SeatMap) -> Result<usize, String> {
+    if let Ok(map_path) = std::env::var("DAY05_MAP") {
+        let file =
+            File::create(&map_path).map_err(|e| format!("Couldn't create {}: {}", map_path, e))?;
+        data.write_grid(BufWriter::new(file))
+            .map_err(|e| format!("Couldn't write {}: {}", map_path, e))?;
+    }
+    Ok(data.vacancies().len())
 }
@@ -1,42 +1,109 @@
-use std::collections::HashSet;
+use itertools::Itertools;
+use std::fmt;
 
+const QUESTIONS: usize = 26;
+
+/// One group's answers, as a mask per person with bit 0 for question `a` up to bit 25 for `z`.
 #[derive(Debug)]
 pub struct Group {
-    answers: Vec<HashSet<char>>,
+    answers: Vec<u32>,
 }
 
 impl Group {
     fn any_questions_answered(&self) -> usize {
-        let mut result: HashSet<char> = HashSet::new();
-        for set in &self.answers {
-            result = &result | set;
-        }
-        result.len()
+        self.answered_by_at_least(1)
     }
 
     fn all_questions_answered(&self) -> usize {
-        if self.answers.is_empty() {
-            return 0;
+        self.answered_by_at_least(self.answers.len())
+    }
+
+    /// Number of people in the group who answered each question.
+    fn question_counts(&self) -> [usize; QUESTIONS] {
+        let mut counts = [0; QUESTIONS];
+        for mask in &self.answers {
+            for (question, count) in counts.iter_mut().enumerate() {
+                if mask & (1 << question) != 0 {
+                    *count += 1;
+                }
+            }
         }
-        let mut result: HashSet<char> = self.answers[0].clone();
-        for set in &self.answers {
-            result = &result & set;
+        counts
+    }
+
+    /// Counts the questions answered by at least `quorum` people.  A question nobody answered
+    /// never counts, even for a quorum of zero.
+    pub fn answered_by_at_least(&self, quorum: usize) -> usize {
+        match quorum {
+            _ if self.answers.is_empty() => 0,
+            1 => self
+                .answers
+                .iter()
+                .fold(0, |any, mask| any | mask)
+                .count_ones() as usize,
+            n if n == self.answers.len() => self
+                .answers
+                .iter()
+                .fold(!0, |all, mask| all & mask)
+                .count_ones() as usize,
+            _ => self
+                .question_counts()
+                .iter()
+                .filter(|&&count| count > 0 && count >= quorum)
+                .count(),
         }
-        result.len()
     }
 }
 
-fn str_to_group(s: &str) -> Group {
-    Group {
-        answers: s
-            .lines()
-            .map(|line| line.chars().collect::<HashSet<_>>())
-            .collect(),
+fn str_to_group(s: &str) -> Result<Group, String> {
+    let answers = s
+        .lines()
+        .map(|line| {
+            line.bytes()
+                .try_fold(0u32, |mask, question| match question {
+                    b'a'..=b'z' => Ok(mask | 1 << (question - b'a')),
+                    other => Err(format!(
+                        "Unexpected {:?} in answers {}",
+                        char::from(other),
+                        line
+                    )),
+                })
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    Ok(Group { answers })
+}
+
+/// How many people answered each question across every group, listed by question.
+#[derive(Debug, Clone)]
+pub struct QuestionHistogram([usize; QUESTIONS]);
+
+impl QuestionHistogram {
+    pub fn new(groups: &[Group]) -> Self {
+        let mut totals = [0; QUESTIONS];
+        for counts in groups.iter().map(Group::question_counts) {
+            for (total, count) in totals.iter_mut().zip(counts.iter()) {
+                *total += count;
+            }
+        }
+        QuestionHistogram(totals)
+    }
+}
+
+impl fmt::Display for QuestionHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries = (b'a'..=b'z')
+            .zip(self.0.iter())
+            .map(|(question, count)| format!("{} {}", char::from(question), count));
+        write!(f, "{}", entries.format(", "))
     }
 }
 
 pub fn generator(input: &str) -> Vec<Group> {
-    input.split("\n\n").map(str_to_group).collect()
+    input
+        .split("\n\n")
+        .map(str_to_group)
+        .collect::<Result<Vec<Group>, String>>()
+        .expect("Error parsing input for day 6!")
 }
 
 pub fn part_one(data: &[Group]) -> usize {
@@ -50,3 +117,14 @@ pub fn part_two(data: &[Group]) -> usize {
         .map(|x| x.all_questions_answered())
         .sum::<usize>()
 }
+
+/// Questions answered by a strict majority of each group.
+pub fn part_majority(data: &[Group]) -> usize {
+    data.iter()
+        .map(|x| x.answered_by_at_least(x.answers.len() / 2 + 1))
+        .sum::<usize>()
+}
+
+pub fn part_histogram(data: &[Group]) -> QuestionHistogram {
+    QuestionHistogram::new(data)
+}
@@ -1,21 +1,28 @@
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
 
+const DEFAULT_BAG: &str = "shiny gold";
+
+/// One line of the rules: a colour and the bags it directly contains.
 #[derive(Debug, Clone)]
 pub struct Bag {
     color: String,
-    bag_value: u32,
-    contains: HashMap<String, u32>,
-    unknown_contains: HashMap<String, u32>,
+    contains: Vec<(String, u32)>,
 }
 
-type BagMap = HashMap<String, Bag>;
-type ReverseBagMap = HashMap<String, Vec<String>>;
+type BagId = usize;
 
 fn line_to_bag(line: &str) -> Result<Bag, String> {
-    let mut contains: HashMap<String, u32> = HashMap::new();
+    let mut contains: Vec<(String, u32)> = Vec::new();
     // Using lazy_static here so that we don't have to recompile the regex every function run
     lazy_static! {
         static ref LINE_REGEX: Regex =
@@ -33,135 +40,618 @@ fn line_to_bag(line: &str) -> Result<Bag, String> {
                 let captures = CONTAINS_REGEX.captures(item).unwrap();
                 let contains_num = captures.name("num").unwrap().as_str().to_string();
                 let contains_color = captures.name("color").unwrap().as_str().to_string();
-                contains.insert(contains_color, contains_num.parse::<u32>().unwrap());
+                contains.push((contains_color, contains_num.parse::<u32>().unwrap()));
             } else {
                 return Err(format!("Couldn't parse contains string {}", item));
             }
         }
-        #[allow(clippy::redundant_clone)]
-        Ok(Bag {
-            color,
-            bag_value: 0,
-            contains: contains.clone(),
-            unknown_contains: contains.clone(),
-        })
+        Ok(Bag { color, contains })
     } else {
         Err(format!("Couldn't parse line {}", line))
     }
 }
 
-fn build_reversed_bag_map(bag_map: &BagMap) -> ReverseBagMap {
-    let mut reversed_bag_map: ReverseBagMap = HashMap::new();
-    for color in bag_map.keys() {
-        let containing_colors = bag_map
-            .values()
-            .filter_map(|bag| {
-                if bag.contains.keys().contains(color) {
-                    Some(bag.color.clone())
-                } else {
-                    None
+/// A sorted list of colours.
+#[derive(Debug, Clone)]
+pub struct BagList(BTreeSet<String>);
+
+impl fmt::Display for BagList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bags: {}", self.0.len(), self.0.iter().join(", "))
+    }
+}
+
+/// Colours from the outermost bag inwards, each directly containing the next.
+#[derive(Debug, Clone)]
+pub struct BagPath(Vec<String>);
+
+impl fmt::Display for BagPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.iter().join(" -> "))
+    }
+}
+
+/// Which part of the graph to export.
+#[derive(Debug, Clone)]
+pub enum Subgraph {
+    All,
+    /// A colour and every bag that can contain it
+    Ancestors(String),
+    /// A colour and every bag that can end up inside it
+    Descendants(String),
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedEdge<'a> {
+    color: &'a str,
+    count: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedBag<'a> {
+    color: &'a str,
+    total: u64,
+    contains: Vec<ExportedEdge<'a>>,
+}
+
+/// One edit to the rules: a new or changed rule, or a colour whose rule goes away.
+#[derive(Debug, Clone)]
+pub enum RuleUpdate {
+    Set(Bag),
+    Remove(String),
+}
+
+impl FromStr for RuleUpdate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("remove ") {
+            Some(color) => Ok(RuleUpdate::Remove(color.trim().to_string())),
+            None => line_to_bag(s).map(RuleUpdate::Set),
+        }
+    }
+}
+
+/// The rules as a graph over interned colour IDs, with edges both ways and the total number of
+/// bags inside each colour worked out up front.  A colour that's only ever mentioned inside
+/// other bags is treated as containing nothing, and once nothing mentions it any more its ID
+/// is left unused rather than renumbering everything.
+#[derive(Debug, Clone)]
+pub struct BagGraph {
+    names: Vec<String>,
+    ids: HashMap<String, BagId>,
+    has_rule: Vec<bool>,
+    contains: Vec<Vec<(BagId, u32)>>,
+    contained_by: Vec<Vec<BagId>>,
+    totals: Vec<u64>,
+}
+
+impl BagGraph {
+    /// Builds the graph, failing if any bag ends up inside itself.  A later rule for the same
+    /// colour replaces an earlier one.
+    pub fn from_rules(rules: &[Bag]) -> Result<Self, String> {
+        let mut graph = BagGraph {
+            names: Vec::new(),
+            ids: HashMap::new(),
+            has_rule: Vec::new(),
+            contains: Vec::new(),
+            contained_by: Vec::new(),
+            totals: Vec::new(),
+        };
+        for rule in rules {
+            let id = graph.intern(&rule.color);
+            graph.has_rule[id] = true;
+            let contains = rule
+                .contains
+                .iter()
+                .map(|(color, count)| (graph.intern(color), *count))
+                .collect();
+            graph.contains[id] = contains;
+        }
+        for (parent, children) in graph.contains.iter().enumerate() {
+            for &(child, _) in children {
+                if !graph.contained_by[child].contains(&parent) {
+                    graph.contained_by[child].push(parent);
                 }
+            }
+        }
+        graph.totals = vec![0; graph.names.len()];
+        let everything: HashSet<BagId> = (0..graph.names.len()).collect();
+        graph.recompute_totals(&everything)?;
+        Ok(graph)
+    }
+
+    fn intern(&mut self, color: &str) -> BagId {
+        if let Some(&id) = self.ids.get(color) {
+            return id;
+        }
+        let id = self.names.len();
+        self.names.push(color.to_string());
+        self.ids.insert(color.to_string(), id);
+        self.has_rule.push(false);
+        self.contains.push(Vec::new());
+        self.contained_by.push(Vec::new());
+        id
+    }
+
+    /// Works out the totals for the `stale` bags in place, trusting everyone else's.  This is
+    /// Kahn's algorithm: a stale bag is ready once none of the bags it contains are still
+    /// stale, so the totals get filled in from the innermost bags outwards.  Anything still
+    /// waiting at the end is waiting on a cycle.
+    fn recompute_totals(&mut self, stale: &HashSet<BagId>) -> Result<(), String> {
+        // How many of each stale bag's rule entries are for bags that are still stale
+        let mut waiting: HashMap<BagId, usize> = stale
+            .iter()
+            .map(|&id| {
+                let stale_children = self.contains[id]
+                    .iter()
+                    .filter(|(child, _)| stale.contains(child))
+                    .count();
+                (id, stale_children)
             })
             .collect();
-        reversed_bag_map.insert(color.clone(), containing_colors);
+        let mut ready: Vec<BagId> = waiting
+            .iter()
+            .filter(|(_, &stale_children)| stale_children == 0)
+            .map(|(&id, _)| id)
+            .collect();
+        while let Some(id) = ready.pop() {
+            waiting.remove(&id);
+            let mut total: u64 = 0;
+            for &(child, count) in &self.contains[id] {
+                total = self.totals[child]
+                    .checked_add(1)
+                    .and_then(|inside| inside.checked_mul(u64::from(count)))
+                    .and_then(|inside| inside.checked_add(total))
+                    .ok_or_else(|| format!("Too many bags inside {} bags", self.names[id]))?;
+            }
+            self.totals[id] = total;
+            for &parent in &self.contained_by[id] {
+                if let Some(stale_children) = waiting.get_mut(&parent) {
+                    let entries = self.contains[parent]
+                        .iter()
+                        .filter(|&&(child, _)| child == id)
+                        .count();
+                    *stale_children -= entries;
+                    if *stale_children == 0 {
+                        ready.push(parent);
+                    }
+                }
+            }
+        }
+
+        // Every bag left is waiting on another one that's left, so following them from any
+        // starting point has to come back round
+        if let Some(&start) = waiting.keys().min() {
+            let mut chain = vec![start];
+            loop {
+                let current = *chain.last().unwrap();
+                let next = self.contains[current]
+                    .iter()
+                    .map(|&(child, _)| child)
+                    .find(|child| waiting.contains_key(child))
+                    .unwrap();
+                if let Some(repeat) = chain.iter().position(|&id| id == next) {
+                    let cycle = chain[repeat..]
+                        .iter()
+                        .chain(std::iter::once(&next))
+                        .map(|&id| &self.names[id])
+                        .join(" -> ");
+                    return Err(format!("Bag rules contain a cycle: {}", cycle));
+                }
+                chain.push(next);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_present(&self, id: BagId) -> bool {
+        self.has_rule[id] || !self.contained_by[id].is_empty()
+    }
+
+    fn id(&self, color: &str) -> Result<BagId, String> {
+        self.ids
+            .get(color)
+            .copied()
+            .filter(|&id| self.is_present(id))
+            .ok_or_else(|| format!("No rule mentions {} bags", color))
+    }
+
+    fn ancestor_ids(&self, id: BagId) -> Vec<bool> {
+        self.reachable(id, |id| self.contained_by[id].clone())
+    }
+
+    /// `id` and every bag that ends up holding it, which are exactly the totals an edit to
+    /// `id`'s rule can change.  Each ancestor maps to the bag it holds on the way down to `id`.
+    fn affected(&self, id: BagId) -> HashMap<BagId, Option<BagId>> {
+        let mut via = HashMap::new();
+        via.insert(id, None);
+        let mut queue: VecDeque<BagId> = VecDeque::new();
+        queue.push_back(id);
+        while let Some(current) = queue.pop_front() {
+            for &parent in &self.contained_by[current] {
+                if let Entry::Vacant(entry) = via.entry(parent) {
+                    entry.insert(Some(current));
+                    queue.push_back(parent);
+                }
+            }
+        }
+        via
     }
-    reversed_bag_map
-}
 
-fn get_all_containing_bags(reversed_bag_map: &ReverseBagMap, to_find: String) -> HashSet<String> {
-    let mut current_set: HashSet<String> = HashSet::new();
-    current_set.insert(to_find);
-    loop {
-        let start_set = current_set.clone();
-        for color in &start_set {
-            let new_bags = reversed_bag_map.get(color.as_str()).unwrap();
-            current_set.extend(new_bags.iter().cloned())
+    /// Adds a rule, or replaces the existing rule for the same colour.  Only the colour and the
+    /// bags that can contain it get their totals recomputed.  A rule that would put a bag inside
+    /// itself is rejected and leaves the graph as it was.
+    pub fn set_rule(&mut self, rule: &Bag) -> Result<(), String> {
+        if let Some((color, _)) = rule.contains.iter().find(|(color, _)| *color == rule.color) {
+            return Err(format!("Bag rules contain a cycle: {} -> {}", color, color));
         }
-        if start_set == current_set {
-            return current_set;
+        // The new rule makes a cycle exactly when one of its bags already holds this one
+        let affected = match self.ids.get(&rule.color) {
+            Some(&id) => self.affected(id),
+            None => HashMap::new(),
+        };
+        for (color, _) in &rule.contains {
+            let mut current = self.ids.get(color).copied();
+            if current.map_or(false, |child| affected.contains_key(&child)) {
+                let mut cycle = vec![rule.color.as_str()];
+                while let Some(bag) = current {
+                    cycle.push(&self.names[bag]);
+                    current = affected[&bag];
+                }
+                return Err(format!("Bag rules contain a cycle: {}", cycle.join(" -> ")));
+            }
         }
+        let id = self.intern(&rule.color);
+        let mut stale: HashSet<BagId> = affected.keys().copied().collect();
+        stale.insert(id);
+        let previous = (self.has_rule[id], self.contains[id].clone());
+        let contains: Vec<(BagId, u32)> = rule
+            .contains
+            .iter()
+            .map(|(color, count)| (self.intern(color), *count))
+            .collect();
+        self.totals.resize(self.names.len(), 0);
+        self.unlink(id);
+        self.link(id, contains);
+        self.has_rule[id] = true;
+        self.recompute_totals(&stale).map_err(|e| {
+            // Put the old rule back so a failed update changes nothing.  Only `id`'s own
+            // entries changed, so the same bags hold it as before.
+            let (had_rule, old_contains) = previous;
+            self.unlink(id);
+            self.link(id, old_contains);
+            self.has_rule[id] = had_rule;
+            self.recompute_totals(&stale)
+                .expect("Totals were fine before the update");
+            e
+        })
+    }
+
+    /// Drops the rule for `color`.  If other rules still mention it, it stays in the graph as a
+    /// bag that contains nothing.
+    pub fn remove_rule(&mut self, color: &str) -> Result<(), String> {
+        let id = self
+            .ids
+            .get(color)
+            .copied()
+            .filter(|&id| self.has_rule[id])
+            .ok_or_else(|| format!("No rule for {} bags", color))?;
+        self.unlink(id);
+        self.has_rule[id] = false;
+        let stale: HashSet<BagId> = self.affected(id).keys().copied().collect();
+        self.recompute_totals(&stale)
     }
-}
 
-fn bag_descendants(bag_map: &BagMap, start_bag: String) -> HashSet<String> {
-    let mut descendants: HashSet<String> = HashSet::new();
-    descendants.insert(start_bag);
-    loop {
-        let start_set = descendants.clone();
-        for color in &start_set {
-            let new_bags = &bag_map.get(color.as_str()).unwrap().contains;
-            descendants.extend(new_bags.keys().cloned())
+    pub fn apply(&mut self, update: &RuleUpdate) -> Result<(), String> {
+        match update {
+            RuleUpdate::Set(rule) => self.set_rule(rule),
+            RuleUpdate::Remove(color) => self.remove_rule(color),
         }
-        if start_set == descendants {
-            return descendants;
+    }
+
+    fn link(&mut self, id: BagId, contains: Vec<(BagId, u32)>) {
+        for &(child, _) in &contains {
+            if !self.contained_by[child].contains(&id) {
+                self.contained_by[child].push(id);
+            }
         }
+        self.contains[id] = contains;
     }
-}
 
-fn generate_bag_values(
-    bag_map: &mut BagMap,
-    reversed_bag_map: &ReverseBagMap,
-) -> HashMap<String, u32> {
-    let mut known_values: HashMap<String, u32> = HashMap::new();
-    for bag in bag_map.values() {
-        if bag.unknown_contains.is_empty() {
-            known_values.insert(bag.color.clone(), bag.bag_value);
+    /// Takes away everything `id` contains, along with the matching reverse edges.
+    fn unlink(&mut self, id: BagId) {
+        for (child, _) in std::mem::take(&mut self.contains[id]) {
+            self.contained_by[child].retain(|&parent| parent != id);
         }
     }
-    loop {
-        let this_pass: Vec<String> = known_values.keys().cloned().collect();
-        for color in &this_pass {
-            for parent_color in &reversed_bag_map[color] {
-                if known_values.contains_key(parent_color.as_str()) {
-                    continue;
+
+    /// The rules that would rebuild this graph from scratch.
+    pub fn rules(&self) -> Vec<Bag> {
+        (0..self.names.len())
+            .filter(|&id| self.has_rule[id])
+            .map(|id| Bag {
+                color: self.names[id].clone(),
+                contains: self.contains[id]
+                    .iter()
+                    .map(|&(child, count)| (self.names[child].clone(), count))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Checks that two graphs give the same answers for every colour, whatever IDs they use.
+    pub fn check_matches(&self, other: &BagGraph) -> Result<(), String> {
+        let colors = |graph: &BagGraph| -> BTreeSet<String> {
+            (0..graph.names.len())
+                .filter(|&id| graph.is_present(id))
+                .map(|id| graph.names[id].clone())
+                .collect()
+        };
+        if colors(self) != colors(other) {
+            return Err(String::from("Graphs have different colours"));
+        }
+        for color in colors(self) {
+            let (id, other_id) = (self.id(&color)?, other.id(&color)?);
+            let rule = |graph: &BagGraph, id: BagId| {
+                graph.contains[id]
+                    .iter()
+                    .map(|&(child, count)| (graph.names[child].clone(), count))
+                    .collect::<Vec<_>>()
+            };
+            if self.has_rule[id] != other.has_rule[other_id]
+                || rule(self, id) != rule(other, other_id)
+            {
+                return Err(format!("Rules for {} bags differ", color));
+            }
+            if self.totals[id] != other.totals[other_id] {
+                return Err(format!("Totals for {} bags differ", color));
+            }
+            if self.ancestors(&color)?.0 != other.ancestors(&color)?.0 {
+                return Err(format!("Ancestors of {} bags differ", color));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every bag reachable from `start` along `edges`, not counting `start` itself unless
+    /// there's a cycle through it.
+    fn reachable(&self, start: BagId, edges: impl Fn(BagId) -> Vec<BagId>) -> Vec<bool> {
+        let mut seen = vec![false; self.names.len()];
+        let mut queue: VecDeque<BagId> = VecDeque::new();
+        queue.push_back(start);
+        while let Some(id) = queue.pop_front() {
+            for next in edges(id) {
+                if !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    fn members(&self, subgraph: &Subgraph) -> Result<Vec<bool>, String> {
+        let (start, mut members) = match subgraph {
+            Subgraph::All => {
+                return Ok((0..self.names.len())
+                    .map(|id| self.is_present(id))
+                    .collect())
+            }
+            Subgraph::Ancestors(color) => {
+                let start = self.id(color)?;
+                (start, self.ancestor_ids(start))
+            }
+            Subgraph::Descendants(color) => {
+                let start = self.id(color)?;
+                let members = self.reachable(start, |id| {
+                    self.contains[id].iter().map(|&(child, _)| child).collect()
+                });
+                (start, members)
+            }
+        };
+        members[start] = true;
+        Ok(members)
+    }
+
+    /// Writes the subgraph as a Graphviz digraph, with each edge labelled by how many of the
+    /// inner bag go in the outer one.
+    pub fn write_dot<W: Write>(&self, subgraph: &Subgraph, mut writer: W) -> Result<(), String> {
+        let members = self.members(subgraph)?;
+        let quoted = |id: BagId| format!("\"{}\"", self.names[id].replace('"', "\\\""));
+        let mut write_all = || -> io::Result<()> {
+            writeln!(writer, "digraph bags {{")?;
+            for id in (0..self.names.len()).filter(|&id| members[id]) {
+                writeln!(writer, "    {};", quoted(id))?;
+            }
+            for id in (0..self.names.len()).filter(|&id| members[id]) {
+                for &(child, count) in self.contains[id]
+                    .iter()
+                    .filter(|&&(child, _)| members[child])
+                {
+                    writeln!(
+                        writer,
+                        "    {} -> {} [label=\"{}\"];",
+                        quoted(id),
+                        quoted(child),
+                        count
+                    )?;
                 }
-                if !bag_map.contains_key(parent_color.as_str()) {
+            }
+            writeln!(writer, "}}")
+        };
+        write_all().map_err(|e| e.to_string())
+    }
+
+    /// Writes the subgraph as a JSON array with one object per bag, giving its colour, the
+    /// total number of bags inside it and the bags it directly contains.
+    pub fn write_json<W: Write>(&self, subgraph: &Subgraph, mut writer: W) -> Result<(), String> {
+        let members = self.members(subgraph)?;
+        let bags: Vec<ExportedBag> = (0..self.names.len())
+            .filter(|&id| members[id])
+            .map(|id| ExportedBag {
+                color: &self.names[id],
+                total: self.totals[id],
+                contains: self.contains[id]
+                    .iter()
+                    .filter(|&&(child, _)| members[child])
+                    .map(|&(child, count)| ExportedEdge {
+                        color: &self.names[child],
+                        count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut writer, &bags).map_err(|e| e.to_string())?;
+        writeln!(writer).map_err(|e| e.to_string())
+    }
+
+    fn bag_list(&self, members: &[bool]) -> BagList {
+        BagList(
+            members
+                .iter()
+                .enumerate()
+                .filter(|(_, &member)| member)
+                .map(|(id, _)| self.names[id].clone())
+                .collect(),
+        )
+    }
+
+    /// Every colour that can eventually contain `color`.
+    pub fn ancestors(&self, color: &str) -> Result<BagList, String> {
+        let ancestors = self.ancestor_ids(self.id(color)?);
+        Ok(self.bag_list(&ancestors))
+    }
+
+    /// Every colour that can end up inside `color`.
+    pub fn descendants(&self, color: &str) -> Result<BagList, String> {
+        let descendants = self.reachable(self.id(color)?, |id| {
+            self.contains[id].iter().map(|&(child, _)| child).collect()
+        });
+        Ok(self.bag_list(&descendants))
+    }
+
+    /// The total number of bags inside one `color` bag.
+    pub fn total_inside(&self, color: &str) -> Result<u64, String> {
+        Ok(self.totals[self.id(color)?])
+    }
+
+    /// The shortest chain of rules that puts an `inner` bag somewhere inside an `outer` one.
+    pub fn path(&self, outer: &str, inner: &str) -> Result<BagPath, String> {
+        let (outer_id, inner_id) = (self.id(outer)?, self.id(inner)?);
+        let mut came_from: Vec<Option<BagId>> = vec![None; self.names.len()];
+        let mut queue: VecDeque<BagId> = VecDeque::new();
+        queue.push_back(outer_id);
+        while let Some(id) = queue.pop_front() {
+            for &(child, _) in &self.contains[id] {
+                if came_from[child].is_some() {
                     continue;
                 }
-                let mut parent_bag = bag_map.get_mut(parent_color.as_str()).unwrap();
-                if parent_bag.unknown_contains.contains_key(color) {
-                    // Add the known value times the number it contains
-                    let new_value = parent_bag.contains[color]
-                        + (known_values[color] * parent_bag.contains[color]);
-                    parent_bag.bag_value += new_value;
-                    parent_bag.unknown_contains.remove(color);
-                    if parent_bag.unknown_contains.is_empty() {
-                        known_values.insert(parent_bag.color.clone(), parent_bag.bag_value);
+                came_from[child] = Some(id);
+                if child == inner_id {
+                    let mut path = vec![self.names[child].clone()];
+                    let mut current = id;
+                    path.push(self.names[current].clone());
+                    while current != outer_id {
+                        current = came_from[current].unwrap();
+                        path.push(self.names[current].clone());
                     }
+                    path.reverse();
+                    return Ok(BagPath(path));
                 }
+                queue.push_back(child);
             }
         }
-        if known_values.len() == this_pass.len() {
-            break;
-        }
+        Err(format!("{} bags can't contain {} bags", outer, inner))
     }
-    known_values
 }
 
-pub fn generator(input: &str) -> (BagMap, ReverseBagMap) {
+/// The colour to ask about, from `DAY07_BAG` or shiny gold by default.
+fn query_bag() -> String {
+    std::env::var("DAY07_BAG").unwrap_or_else(|_| DEFAULT_BAG.to_string())
+}
+
+pub fn generator(input: &str) -> Result<BagGraph, String> {
     let bag_list = input
         .lines()
         .map(line_to_bag)
-        .collect::<Result<Vec<Bag>, String>>()
-        .expect("Couldn't parse input for day 7!");
-    let mut bag_map: BagMap = HashMap::new();
-    for bag in bag_list {
-        bag_map.insert(bag.color.clone(), bag.to_owned());
+        .collect::<Result<Vec<Bag>, String>>()?;
+    BagGraph::from_rules(&bag_list)
+}
+
+pub fn part_one(data: &BagGraph) -> usize {
+    data.ancestors(DEFAULT_BAG)
+        .expect("No shiny gold bags in day 7 input!")
+        .0
+        .len()
+}
+
+pub fn part_two(data: &BagGraph) -> u64 {
+    data.total_inside(DEFAULT_BAG)
+        .expect("No shiny gold bags in day 7 input!")
+}
+
+pub fn part_ancestors(data: &BagGraph) -> Result<BagList, String> {
+    data.ancestors(&query_bag())
+}
+
+pub fn part_descendants(data: &BagGraph) -> Result<BagList, String> {
+    data.descendants(&query_bag())
+}
+
+pub fn part_total(data: &BagGraph) -> Result<u64, String> {
+    data.total_inside(&query_bag())
+}
+
+/// Writes the graph to the path in `DAY07_EXPORT`, if it's set: JSON for `.json` files and DOT
+/// otherwise.  `DAY07_EXPORT_SUBGRAPH` can be `ancestors` or `descendants` to export only the
+/// query colour and the bags around it.  Returns the number of bags exported.
+pub fn part_export(data: &BagGraph) -> Result<usize, String> {
+    let subgraph = match std::env::var("DAY07_EXPORT_SUBGRAPH").as_deref() {
+        Err(_) | Ok("all") => Subgraph::All,
+        Ok("ancestors") => Subgraph::Ancestors(query_bag()),
+        Ok("descendants") => Subgraph::Descendants(query_bag()),
+        Ok(other) => return Err(format!("Unknown subgraph {}", other)),
+    };
+    if let Ok(export_path) = std::env::var("DAY07_EXPORT") {
+        let path = Path::new(&export_path);
+        let file =
+            File::create(path).map_err(|e| format!("Couldn't create {}: {}", export_path, e))?;
+        let writer = BufWriter::new(file);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => data.write_json(&subgraph, writer),
+            _ => data.write_dot(&subgraph, writer),
+        }
+        .map_err(|e| format!("Couldn't write {}: {}", export_path, e))?;
     }
-    let reversed_bag_map = build_reversed_bag_map(&bag_map);
-    (bag_map, reversed_bag_map)
+    Ok(data
+        .members(&subgraph)?
+        .iter()
+        .filter(|&&member| member)
+        .count())
 }
 
-pub fn part_one(data: &(BagMap, ReverseBagMap)) -> usize {
-    let (_, reversed_bag_map) = data;
-    get_all_containing_bags(reversed_bag_map, "shiny gold".to_string()).len() - 1
+/// Applies the updates in the file named by `DAY07_UPDATES`, one per line: either a rule, which
+/// adds or replaces the rule for that colour, or `remove <colour>`.  The result is checked
+/// against a full rebuild from the updated rules, and the query colour's new total returned.
+pub fn part_update(data: &BagGraph) -> Result<u64, String> {
+    let mut graph = data.clone();
+    if let Ok(updates_path) = std::env::var("DAY07_UPDATES") {
+        let updates = std::fs::read_to_string(&updates_path)
+            .map_err(|e| format!("Couldn't read {}: {}", updates_path, e))?;
+        for (i, line) in updates.lines().enumerate() {
+            line.parse::<RuleUpdate>()
+                .and_then(|update| graph.apply(&update))
+                .map_err(|e| format!("Update {}: {}", i + 1, e))?;
+        }
+        graph.check_matches(&BagGraph::from_rules(&graph.rules())?)?;
+    }
+    graph.total_inside(&query_bag())
 }
 
-pub fn part_two(data: &(BagMap, ReverseBagMap)) -> u32 {
-    let (bag_map, reversed_bag_map) = data;
-    let mut my_bag_map = bag_map.clone();
-    let relevant_bags = bag_descendants(&bag_map, "shiny gold".to_string());
-    my_bag_map.retain(|_, bag| relevant_bags.contains(&bag.color));
-    let known_bag_values = generate_bag_values(&mut my_bag_map, reversed_bag_map);
-    known_bag_values[&"shiny gold".to_string()]
+/// The path from the colour in `DAY07_OUTER_BAG` down to the query colour.
+pub fn part_path(data: &BagGraph) -> Result<BagPath, String> {
+    let outer = std::env::var("DAY07_OUTER_BAG")
+        .map_err(|_| String::from("Set DAY07_OUTER_BAG to find a path"))?;
+    data.path(&outer, &query_bag())
 }
@@ -1,16 +1,31 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
 use std::str::FromStr;
 use std::mem::discriminant;
-use std::collections::HashSet;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Operation {
     Nop,
     Acc,
     Jmp,
 }
 
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Nop => write!(f, "nop"),
+            Operation::Acc => write!(f, "acc"),
+            Operation::Jmp => write!(f, "jmp"),
+        }
+    }
+}
+
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Instruction {
     opcode: Operation,
     arg: i16,
@@ -58,42 +73,674 @@ impl FromStr for Instruction {
     }
 }
 
-type ProgramResult = (i32, bool);
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:+}", self.opcode, self.arg)
+    }
+}
+
+/// Assembles a program written with optional labels and comments.  A label is a name followed
+/// by `:`, either on its own line or before an instruction, and `jmp` and `nop` can take a
+/// label instead of an offset.  Everything after a `#` is a comment.
+///
+/// ```text
+/// loop: acc +1   # count up forever
+///       jmp loop
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<Instruction>, String> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    // Line number and text of each instruction, in program order
+    let mut lines: Vec<(usize, &str)> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut text = line.split('#').next().unwrap_or("").trim();
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            let valid = label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(format!("Line {}: bad label '{}'", i + 1, label));
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(format!(
+                    "Line {}: label {} is already defined",
+                    i + 1,
+                    label
+                ));
+            }
+            text = rest.trim();
+        }
+        if !text.is_empty() {
+            lines.push((i + 1, text));
+        }
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(pc, &(line_number, text))| {
+            let parts: Vec<&str> = text.split_whitespace().collect();
+            let (opcode, arg) = match parts.as_slice() {
+                ["nop", arg] => (Operation::Nop, *arg),
+                ["acc", arg] => (Operation::Acc, *arg),
+                ["jmp", arg] => (Operation::Jmp, *arg),
+                _ => return Err(format!("Line {}: couldn't parse '{}'", line_number, text)),
+            };
+            let arg = match (arg.parse::<i16>(), labels.get(arg)) {
+                (Ok(arg), _) => arg,
+                (Err(_), Some(&target)) if opcode != Operation::Acc => {
+                    i16::try_from(target as i64 - pc as i64).map_err(|_| {
+                        format!("Line {}: label {} is too far away", line_number, arg)
+                    })?
+                }
+                (Err(_), _) => return Err(format!("Line {}: bad argument '{}'", line_number, arg)),
+            };
+            Ok(Instruction { opcode, arg })
+        })
+        .collect()
+}
+
+/// A program listing with each instruction's address, and where each `jmp` goes, in comments.
+/// The listing assembles back to the same program.
+#[derive(Debug, Clone)]
+pub struct Disassembly<'a>(&'a [Instruction]);
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let len = self.0.len();
+        for (pc, instruction) in self.0.iter().enumerate() {
+            let line = format!("{:<10} # {}", instruction.to_string(), pc);
+            match instruction.opcode {
+                Operation::Jmp => match successor(pc, instruction.opcode, instruction.arg, len) {
+                    Some(target) if target == len => writeln!(f, "{} -> end", line)?,
+                    Some(target) => writeln!(f, "{} -> {}", line, target)?,
+                    None => writeln!(
+                        f,
+                        "{} -> {} (out of bounds)",
+                        line,
+                        pc as i64 + i64::from(instruction.arg)
+                    )?,
+                },
+                Operation::Acc | Operation::Nop => writeln!(f, "{}", line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a machine stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Halt {
+    /// Execution reached the instruction just past the end of the program
+    Terminated,
+    /// The instruction at `pc` was about to run for a second time
+    InfiniteLoop { pc: usize },
+    /// The `jmp` at `pc` tried to go somewhere other than the program or the end of it
+    JumpOutOfBounds { pc: usize, target: i64 },
+    /// The machine ran as many instructions as it was allowed to
+    StepLimit,
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::Terminated => write!(f, "terminated"),
+            Halt::InfiniteLoop { pc } => write!(f, "infinite loop at {}", pc),
+            Halt::JumpOutOfBounds { pc, target } => {
+                write!(f, "jump out of bounds at {} to {}", pc, target)
+            }
+            Halt::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+/// A handheld console running a program one instruction at a time.
+#[derive(Debug, Clone)]
+pub struct Machine<'a> {
+    program: &'a [Instruction],
+    pc: usize,
+    acc: i32,
+    visited: Vec<bool>,
+    steps: usize,
+    step_limit: Option<usize>,
+    halted: Option<Halt>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Machine {
+            program,
+            pc: 0,
+            acc: 0,
+            visited: vec![false; program.len()],
+            steps: 0,
+            step_limit: None,
+            halted: None,
+        }
+    }
+
+    pub fn with_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = Some(step_limit);
+        self
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn acc(&self) -> i32 {
+        self.acc
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn halted(&self) -> Option<Halt> {
+        self.halted
+    }
+
+    /// Runs the instruction at `pc`, or returns why the machine can't.  Once a machine has
+    /// halted it stays halted, with `pc` and `acc` as they were when it stopped.
+    pub fn step(&mut self) -> Option<Halt> {
+        if self.halted.is_none() {
+            self.halted = self.try_step();
+        }
+        self.halted
+    }
+
+    fn try_step(&mut self) -> Option<Halt> {
+        if self.pc == self.program.len() {
+            return Some(Halt::Terminated);
+        }
+        if self.visited[self.pc] {
+            return Some(Halt::InfiniteLoop { pc: self.pc });
+        }
+        if self.step_limit.map_or(false, |limit| self.steps >= limit) {
+            return Some(Halt::StepLimit);
+        }
+        self.visited[self.pc] = true;
+        self.steps += 1;
+        let Instruction { opcode, arg } = self.program[self.pc];
+        match opcode {
+            Operation::Acc => {
+                self.acc += i32::from(arg);
+                self.pc += 1;
+            }
+            Operation::Jmp => {
+                let target = self.pc as i64 + i64::from(arg);
+                if target < 0 || target > self.program.len() as i64 {
+                    return Some(Halt::JumpOutOfBounds {
+                        pc: self.pc,
+                        target,
+                    });
+                }
+                self.pc = target as usize;
+            }
+            Operation::Nop => {
+                self.pc += 1;
+            }
+        }
+        None
+    }
+
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Some(halt) = self.step() {
+                return halt;
+            }
+        }
+    }
+}
+
+/// Where execution goes after running `opcode` with `arg` at `pc`, or `None` for a jump that
+/// leaves the program.  The end of the program, `len`, is a valid place to go.
+fn successor(pc: usize, opcode: Operation, arg: i16, len: usize) -> Option<usize> {
+    let target = match opcode {
+        Operation::Jmp => pc as i64 + i64::from(arg),
+        Operation::Acc | Operation::Nop => pc as i64 + 1,
+    };
+    if target < 0 || target > len as i64 {
+        None
+    } else {
+        Some(target as usize)
+    }
+}
+
+/// What a `jmp` or `nop` becomes when it's flipped; an `acc` can't be.
+fn flipped(opcode: Operation) -> Option<Operation> {
+    match opcode {
+        Operation::Jmp => Some(Operation::Nop),
+        Operation::Nop => Some(Operation::Jmp),
+        Operation::Acc => None,
+    }
+}
+
+/// Flipping the instruction at `pc` between `jmp` and `nop` makes the program terminate with
+/// `acc` in the accumulator.
+#[derive(Debug, Copy, Clone)]
+pub struct Repair {
+    pc: usize,
+    from: Operation,
+    to: Operation,
+    acc: i32,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} at {} (acc {})",
+            self.from, self.to, self.pc, self.acc
+        )
+    }
+}
+
+/// Finds every single `jmp`/`nop` flip that makes the program terminate, in O(n).
+///
+/// Every instruction has exactly one successor, so walking the reversed edges back from the
+/// end finds each instruction that already terminates, along with the accumulator total it
+/// adds on the way.  For each instruction the unmodified program runs, we check whether its
+/// flipped successor is one that terminates.  Flipping an instruction it never runs changes
+/// nothing, so that's only a repair if the unmodified program terminates already.
+pub fn find_repairs(program: &[Instruction]) -> Vec<Repair> {
+    let len = program.len();
 
-// Adapted from https://stackoverflow.com/a/54035801
-fn fancy_add(u: usize, i: &i16) -> Option<usize> {
-    if i.is_negative() {
-        u.checked_sub(i.wrapping_abs() as u32 as usize)
+    // The unmodified path, with each instruction's position along it and the accumulator
+    // before it runs
+    let mut position: Vec<Option<usize>> = vec![None; len + 1];
+    let mut path: Vec<(usize, i32)> = Vec::new();
+    let (mut pc, mut acc) = (0, 0);
+    while pc < len && position[pc].is_none() {
+        position[pc] = Some(path.len());
+        path.push((pc, acc));
+        let Instruction { opcode, arg } = program[pc];
+        if opcode == Operation::Acc {
+            acc += i32::from(arg);
+        }
+        match successor(pc, opcode, arg, len) {
+            Some(next) => pc = next,
+            None => break,
+        }
+    }
+
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); len + 1];
+    for (pc, instruction) in program.iter().enumerate() {
+        if let Some(next) = successor(pc, instruction.opcode, instruction.arg, len) {
+            predecessors[next].push(pc);
+        }
+    }
+    // For each instruction that reaches the end: what's left to add to the accumulator, and
+    // the position of the first instruction on the unmodified path it runs into, if any
+    let mut acc_to_end: Vec<Option<i32>> = vec![None; len + 1];
+    let mut joins_path: Vec<Option<usize>> = vec![None; len + 1];
+    acc_to_end[len] = Some(0);
+    let mut queue = vec![len];
+    while let Some(next) = queue.pop() {
+        for &pc in &predecessors[next] {
+            let added = match program[pc].opcode {
+                Operation::Acc => i32::from(program[pc].arg),
+                Operation::Jmp | Operation::Nop => 0,
+            };
+            acc_to_end[pc] = acc_to_end[next].map(|acc| acc + added);
+            joins_path[pc] = position[pc].or(joins_path[next]);
+            queue.push(pc);
+        }
+    }
+
+    // The walk only stops at `len` if the unmodified program terminates
+    let untouched: Vec<Repair> = if pc == len {
+        program
+            .iter()
+            .enumerate()
+            .filter(|&(pc, _)| position[pc].is_none())
+            .filter_map(|(pc, instruction)| {
+                Some(Repair {
+                    pc,
+                    from: instruction.opcode,
+                    to: flipped(instruction.opcode)?,
+                    acc,
+                })
+            })
+            .collect()
     } else {
-        u.checked_add(*i as usize)
+        Vec::new()
+    };
+
+    let mut repairs: Vec<Repair> = path
+        .iter()
+        .enumerate()
+        .filter_map(|(step, &(pc, acc))| {
+            let Instruction { opcode, arg } = program[pc];
+            let flipped = flipped(opcode)?;
+            let next = successor(pc, flipped, arg, len)?;
+            // Running back into the path at or before the flip would run the flipped
+            // instruction again.  That can only happen if the unmodified program terminates.
+            if joins_path[next].map_or(false, |join_step| join_step <= step) {
+                return None;
+            }
+            Some(Repair {
+                pc,
+                from: opcode,
+                to: flipped,
+                acc: acc + acc_to_end[next]?,
+            })
+        })
+        .chain(untouched)
+        .collect();
+    repairs.sort_by_key(|repair| repair.pc);
+    repairs
+}
+
+const DEFAULT_HISTORY: usize = 10;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn holds(self, left: i32, right: i32) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "<" => Ok(Comparison::Less),
+            "<=" => Ok(Comparison::LessOrEqual),
+            "=" | "==" => Ok(Comparison::Equal),
+            "!=" => Ok(Comparison::NotEqual),
+            ">=" => Ok(Comparison::GreaterOrEqual),
+            ">" => Ok(Comparison::Greater),
+            _ => Err(format!("Unknown comparison {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Where `continue` should stop: before running the instruction at a `pc`, or before running
+/// any instruction while `acc` meets a condition.  Written as `pc 12` or `acc >= 100`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Breakpoint {
+    Pc(usize),
+    Acc(Comparison, i32),
+}
+
+impl Breakpoint {
+    fn hit(&self, machine: &Machine) -> bool {
+        match *self {
+            Breakpoint::Pc(pc) => machine.pc() == pc,
+            Breakpoint::Acc(comparison, value) => comparison.holds(machine.acc(), value),
+        }
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            ["pc", pc] => pc
+                .parse()
+                .map(Breakpoint::Pc)
+                .map_err(|_| format!("Couldn't parse pc {}", pc)),
+            ["acc", comparison, value] => Ok(Breakpoint::Acc(
+                comparison.parse()?,
+                value
+                    .parse()
+                    .map_err(|_| format!("Couldn't parse acc value {}", value))?,
+            )),
+            _ => Err(format!("Couldn't parse breakpoint '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(pc) => write!(f, "pc {}", pc),
+            Breakpoint::Acc(comparison, value) => write!(f, "acc {} {}", comparison, value),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Command {
+    /// Run this many instructions, ignoring breakpoints
+    Step(usize),
+    /// Run until a breakpoint or the machine halts
+    Continue,
+    Break(Breakpoint),
+    /// Remove the breakpoint with this number
+    Delete(usize),
+    Breakpoints,
+    State,
+    /// Show this many of the most recently run instructions
+    History(usize),
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let count = |count: &str| {
+            count
+                .parse::<usize>()
+                .map_err(|_| format!("Couldn't parse count {}", count))
+        };
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            ["step"] | ["s"] => Ok(Command::Step(1)),
+            ["step", steps] | ["s", steps] => Ok(Command::Step(count(steps)?)),
+            ["continue"] | ["c"] => Ok(Command::Continue),
+            ["break", breakpoint @ ..] | ["b", breakpoint @ ..] => {
+                Ok(Command::Break(breakpoint.join(" ").parse()?))
+            }
+            ["delete", number] | ["d", number] => Ok(Command::Delete(count(number)?)),
+            ["breakpoints"] => Ok(Command::Breakpoints),
+            ["state"] | ["p"] => Ok(Command::State),
+            ["history"] | ["h"] => Ok(Command::History(DEFAULT_HISTORY)),
+            ["history", entries] | ["h", entries] => Ok(Command::History(count(entries)?)),
+            ["quit"] | ["q"] => Ok(Command::Quit),
+            _ => Err(format!("Unknown command '{}'", s)),
+        }
     }
 }
 
-fn run_program(data: &[Instruction]) -> ProgramResult {
-    let mut acc: i32 = 0;
-    let mut pc: usize = 0;
-    let mut seen: HashSet<usize> = HashSet::new();
-    loop {
-        let current = &data[pc];
-        seen.insert(pc);
-        match current {
-            Instruction {opcode: Operation::Acc, arg} => {
-                acc += i32::from(*arg);
-                pc += 1;
-            },
-            Instruction {opcode: Operation::Jmp, arg} => {
-                pc = fancy_add(pc, arg).unwrap();
-            },
-            Instruction {opcode: Operation::Nop, arg: _} => {
-                pc += 1;
+/// A machine under the control of debugger commands, remembering every instruction it's run
+/// so it can show where it's been and which instructions make up a loop.
+#[derive(Debug, Clone)]
+pub struct Debugger<'a> {
+    machine: Machine<'a>,
+    breakpoints: Vec<Breakpoint>,
+    // The pc and acc before each instruction that's run, in order
+    trace: Vec<(usize, i32)>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Debugger {
+            machine: Machine::new(program),
+            breakpoints: Vec::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    fn step_once(&mut self) -> Option<Halt> {
+        let before = (self.machine.pc(), self.machine.acc());
+        let halt = self.machine.step();
+        if halt.is_none() {
+            self.trace.push(before);
+        }
+        halt
+    }
+
+    fn describe(&self, pc: usize) -> String {
+        match self.machine.program.get(pc) {
+            Some(instruction) => format!("{}: {}", pc, instruction),
+            None => format!("{}: end", pc),
+        }
+    }
+
+    /// The instructions from the first run of `pc` back round to it again.
+    fn describe_loop(&self, pc: usize) -> String {
+        let start = self
+            .trace
+            .iter()
+            .position(|&(traced_pc, _)| traced_pc == pc)
+            .unwrap_or(self.trace.len());
+        let cycle = self.trace[start..]
+            .iter()
+            .map(|&(traced_pc, _)| traced_pc)
+            .chain(std::iter::once(pc))
+            .join(" -> ");
+        format!(
+            "instruction {} is the first to run twice, in a loop of {}: {}",
+            pc,
+            self.trace.len() - start,
+            cycle
+        )
+    }
+
+    pub fn state(&self) -> String {
+        let state = format!(
+            "pc {}, acc {}, {} steps",
+            self.describe(self.machine.pc()),
+            self.machine.acc(),
+            self.machine.steps()
+        );
+        match self.machine.halted() {
+            None => state,
+            Some(Halt::InfiniteLoop { pc }) => {
+                format!("{}; halted: {}", state, self.describe_loop(pc))
             }
+            Some(halt) => format!("{}; halted: {}", state, halt),
         }
-        if seen.contains(&pc) {
-            return (acc, true);
+    }
+
+    fn history(&self, entries: usize) -> String {
+        let start = self.trace.len().saturating_sub(entries);
+        self.trace[start..]
+            .iter()
+            .map(|&(pc, acc)| format!("{:<16} acc {}", self.describe(pc), acc))
+            .join("\n")
+    }
+
+    pub fn execute(&mut self, command: Command) -> String {
+        match command {
+            Command::Step(steps) => {
+                for _ in 0..steps {
+                    if self.step_once().is_some() {
+                        break;
+                    }
+                }
+                self.state()
+            }
+            Command::Continue => {
+                // Always make some progress, so continuing from a breakpoint doesn't stop at it
+                // straight away
+                let mut halt = self.step_once();
+                while halt.is_none() {
+                    let hit = self.breakpoints.iter().position(|bp| bp.hit(&self.machine));
+                    if let Some(number) = hit {
+                        return format!(
+                            "breakpoint {} ({}); {}",
+                            number,
+                            self.breakpoints[number],
+                            self.state()
+                        );
+                    }
+                    halt = self.step_once();
+                }
+                self.state()
+            }
+            Command::Break(breakpoint) => {
+                self.breakpoints.push(breakpoint);
+                format!("breakpoint {}: {}", self.breakpoints.len() - 1, breakpoint)
+            }
+            Command::Delete(number) if number < self.breakpoints.len() => {
+                format!("deleted breakpoint {}", self.breakpoints.remove(number))
+            }
+            Command::Delete(number) => format!("no breakpoint {}", number),
+            Command::Breakpoints if self.breakpoints.is_empty() => String::from("no breakpoints"),
+            Command::Breakpoints => self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(number, breakpoint)| format!("{}: {}", number, breakpoint))
+                .join("\n"),
+            Command::State => self.state(),
+            Command::History(_) if self.trace.is_empty() => String::from("nothing run yet"),
+            Command::History(entries) => self.history(entries),
+            Command::Quit => self.state(),
         }
-        if pc >= data.len() {
-            return (acc, false);
+    }
+
+    /// Reads commands a line at a time until `quit` or the end of the input.  An empty line
+    /// repeats the last command.  With `echo` set each command is written out before its
+    /// result, which makes the transcript readable when the commands come from a file.
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+        echo: bool,
+    ) -> io::Result<()> {
+        let mut last: Option<Command> = None;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(debug) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            if echo {
+                writeln!(output, "{}", line)?;
+            }
+            let command = match (line.trim(), last) {
+                ("", Some(command)) => Ok(command),
+                (text, _) => text.parse::<Command>(),
+            };
+            match command {
+                Ok(Command::Quit) => break,
+                Ok(command) => {
+                    writeln!(output, "{}", self.execute(command))?;
+                    last = Some(command);
+                }
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
         }
+        writeln!(output)
     }
 }
 
@@ -106,19 +753,112 @@ pub fn generator(input: &str) -> Vec<Instruction> {
 }
 
 pub fn part_one(data: &[Instruction]) -> i32 {
-    let (acc, _) = run_program(data);
-    acc
+    let mut machine = Machine::new(data);
+    machine.run();
+    machine.acc()
 }
 
-pub fn part_two(data: &[Instruction]) -> i32 {
-    let mut current_swap: usize = 0;
-    loop {
+pub fn part_two(data: &[Instruction]) -> Result<i32, String> {
+    for current_swap in 0..data.len() {
+        if let Operation::Acc = data[current_swap].opcode {
+            continue;
+        }
         let mut modified_program = data.to_owned();
         modified_program[current_swap] = modified_program[current_swap].swap_opcode(Operation::Jmp, Operation::Nop);
-        let (acc, looped) = run_program(&modified_program);
-        if !looped {
-            return acc;
+        let mut machine = Machine::new(&modified_program);
+        if machine.run() == Halt::Terminated {
+            return Ok(machine.acc());
         }
-        current_swap += 1;
     }
+    Err(String::from("No single jmp/nop flip makes the program terminate"))
+}
+
+/// Part two by control-flow analysis instead of brute force, taking the same repair it would.
+pub fn part_two_cfg(data: &[Instruction]) -> Result<i32, String> {
+    find_repairs(data)
+        .first()
+        .map(|repair| repair.acc)
+        .ok_or_else(|| String::from("No single jmp/nop flip makes the program terminate"))
+}
+
+pub fn part_repairs(data: &[Instruction]) -> String {
+    let repairs = find_repairs(data);
+    if repairs.is_empty() {
+        String::from("no repairs")
+    } else {
+        repairs.iter().join(", ")
+    }
+}
+
+/// Writes a disassembly of the program to the path in `DAY08_DISASSEMBLY`, if it's set, after
+/// checking that both the plain listing and the disassembly read back as the same program.
+/// Returns the number of instructions.
+pub fn part_disassemble(data: &[Instruction]) -> Result<usize, String> {
+    let listing = data.iter().join("\n");
+    if generator(&listing) != data {
+        return Err(String::from(
+            "Listing doesn't parse back to the same program",
+        ));
+    }
+    let disassembly = Disassembly(data).to_string();
+    if assemble(&disassembly)? != data {
+        return Err(String::from(
+            "Disassembly doesn't assemble back to the same program",
+        ));
+    }
+    if let Ok(disassembly_path) = std::env::var("DAY08_DISASSEMBLY") {
+        std::fs::write(&disassembly_path, disassembly)
+            .map_err(|e| format!("Couldn't write {}: {}", disassembly_path, e))?;
+    }
+    Ok(data.len())
+}
+
+/// Assembles the source in the file named by `DAY08_SOURCE` and runs it.
+pub fn part_assemble(_data: &[Instruction]) -> Result<String, String> {
+    let source_path = std::env::var("DAY08_SOURCE")
+        .map_err(|_| String::from("Set DAY08_SOURCE to assemble a program"))?;
+    let source = std::fs::read_to_string(&source_path)
+        .map_err(|e| format!("Couldn't read {}: {}", source_path, e))?;
+    let program = assemble(&source)?;
+    let mut machine = Machine::new(&program);
+    let halt = machine.run();
+    Ok(format!("{} (acc {})", halt, machine.acc()))
+}
+
+/// Debugs the program with commands from the file named by `DAY08_DEBUG`, or from stdin if
+/// it's `-`, and returns the final state.
+pub fn part_debug(data: &[Instruction]) -> Result<String, String> {
+    let commands = std::env::var("DAY08_DEBUG")
+        .map_err(|_| String::from("Set DAY08_DEBUG to a command file or - to debug"))?;
+    let mut debugger = Debugger::new(data);
+    let stdout = io::stdout();
+    if commands == "-" {
+        let stdin = io::stdin();
+        debugger.run(stdin.lock(), stdout.lock(), false)
+    } else {
+        let file =
+            File::open(&commands).map_err(|e| format!("Couldn't open {}: {}", commands, e))?;
+        debugger.run(BufReader::new(file), stdout.lock(), true)
+    }
+    .map_err(|e| format!("Debugger I/O failed: {}", e))?;
+    Ok(debugger.state())
+}
+
+/// How the unmodified program halts, optionally stopping after `DAY08_STEP_LIMIT` instructions.
+pub fn part_halt(data: &[Instruction]) -> Result<String, String> {
+    let mut machine = Machine::new(data);
+    if let Ok(step_limit) = std::env::var("DAY08_STEP_LIMIT") {
+        let step_limit = step_limit
+            .parse()
+            .map_err(|e| format!("Couldn't parse step limit {}: {}", step_limit, e))?;
+        machine = machine.with_step_limit(step_limit);
+    }
+    let halt = machine.run();
+    Ok(format!(
+        "{} after {} steps (pc {}, acc {})",
+        halt,
+        machine.steps(),
+        machine.pc(),
+        machine.acc()
+    ))
 }
@@ -1,3 +1,4 @@
+#![recursion_limit = "256"]
 #[macro_use(scan_fmt)]
 extern crate scan_fmt;
 // #[macro_use(lazy_static)]
@@ -26,14 +27,14 @@ mod day19;
 
 aoc_main::main! {
     year 2020;
-    day01 : generator => part_one, part_two;
-    day02 : generator => part_one, part_two;
-    day03 : generator => part_one, part_two;
-    day04 : generator => part_one, part_two;
-    day05 : generator => part_one, part_two;
-    day06 : generator => part_one, part_two;
-    day07 : generator => part_one, part_two;
-    day08 : generator => part_one, part_two;
+    day01 : generator => part_one, part_one_hashset, part_two, part_two_two_pointer, part_two_mitm, part_two_all, part_two_every_year, part_subset_sum?, part_two_money?;
+    day02 : generator => part_one, part_two, part_named_policies?, part_audit?, part_parallel?;
+    day03 : generator? => part_one, part_two, part_explore?, part_overlay?, part_least_trees?;
+    day04 : generator => part_one, part_two, part_schema?, part_violations?, part_strict?, part_normalise?;
+    day05 : generator? => part_one?, part_two?, part_two_pass?, part_vacancies, part_map?;
+    day06 : generator => part_one, part_two, part_majority, part_histogram;
+    day07 : generator? => part_one, part_two, part_ancestors?, part_descendants?, part_total?, part_path?, part_export?, part_update?;
+    day08 : generator => part_one, part_two?, part_two_cfg?, part_repairs, part_halt?, part_disassemble?, part_assemble?, part_debug?;
     day09 : generator => part_one, part_two;
     day10 : generator => part_one, part_two;
     day11 : generator => part_one_vecgrid, part_one_hashgrid, part_two_vecgrid, part_two_hashgrid;